@@ -0,0 +1,675 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use libc;
+use toml;
+
+use git::{Backend, ConflictRule, ConflictStrategy, PullStrategy, Timeouts};
+
+/// where the effective value of a setting came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self
+        {
+            Source::Default => write!(f, "default"),
+            Source::File => write!(f, "config file"),
+            Source::Env => write!(f, "environment"),
+            Source::Cli => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: Source,
+}
+
+/// one layer of configuration values, every value is optional
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Layer {
+    pub repo: Option<String>,
+    pub name: Option<String>,
+    pub remote: Option<String>,
+    pub branch: Option<String>,
+    pub notify: Option<bool>,
+    pub log_level: Option<String>,
+    pub pull_strategy: Option<PullStrategy>,
+    pub conflict: Option<ConflictStrategy>,
+    pub conflicts: Vec<RuleLayer>,
+    pub backend: Option<Backend>,
+    pub timeouts: TimeoutLayer,
+    pub repos: BTreeMap<String, RepoLayer>,
+}
+
+/// a conflict strategy for the paths matching `glob`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleLayer {
+    pub glob: String,
+    pub strategy: ConflictStrategy,
+}
+
+/// the settings of one named repository in the config file
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoLayer {
+    pub path: Option<String>,
+    pub name: Option<String>,
+    pub remote: Option<String>,
+    pub branch: Option<String>,
+    pub pull_strategy: Option<PullStrategy>,
+    pub conflict: Option<ConflictStrategy>,
+    pub conflicts: Vec<RuleLayer>,
+    pub backend: Option<Backend>,
+    pub timeouts: TimeoutLayer,
+}
+
+/// the time limits of the git operations in seconds
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutLayer {
+    pub fetch: Option<u64>,
+    pub pull: Option<u64>,
+    pub push: Option<u64>,
+    pub submodules: Option<u64>,
+    pub local: Option<u64>,
+}
+
+impl Layer {
+    /// reads the layer from the `GIFSY_*` variables
+    pub fn from_env() -> Result<Layer, ConfigError> {
+        Layer::from_vars(|k| env::var(k).ok())
+    }
+
+    pub fn from_vars<F>(var: F) -> Result<Layer, ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let notify = match var("GIFSY_NOTIFY")
+        {
+            Some(n) => Some(parse_bool("GIFSY_NOTIFY", &n)?),
+            None => None,
+        };
+        let pull_strategy = match var("GIFSY_PULL_STRATEGY")
+        {
+            Some(p) => Some(p.parse().map_err(ConfigError::Invalid)?),
+            None => None,
+        };
+        let conflict = match var("GIFSY_CONFLICT")
+        {
+            Some(c) => Some(c.parse().map_err(ConfigError::Invalid)?),
+            None => None,
+        };
+        let backend = match var("GIFSY_BACKEND")
+        {
+            Some(b) => Some(b.parse().map_err(ConfigError::Invalid)?),
+            None => None,
+        };
+        Ok(Layer {
+            repo: var("GIFSY_REPO"),
+            name: var("GIFSY_NAME"),
+            remote: var("GIFSY_REMOTE"),
+            branch: var("GIFSY_BRANCH"),
+            notify,
+            log_level: var("GIFSY_LOG"),
+            pull_strategy,
+            conflict,
+            conflicts: Vec::new(),
+            backend,
+            timeouts: TimeoutLayer::default(),
+            repos: BTreeMap::new(),
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Layer, ConfigError> {
+        let content = match fs::read_to_string(path)
+        {
+            Ok(c) => c,
+            Err(e) => return Err(ConfigError::Io(path.to_path_buf(), e)),
+        };
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(path.to_path_buf(), e.to_string()))
+    }
+}
+
+/// the effective configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub file: Option<PathBuf>,
+    pub notify: Setting<bool>,
+    pub log_level: Setting<String>,
+    pub repos: Vec<RepoConfig>,
+}
+
+/// the effective configuration of one repository
+#[derive(Debug, Clone)]
+pub struct RepoConfig {
+    pub id: String,
+    pub path: Setting<String>,
+    pub name: Setting<String>,
+    pub remote: Setting<String>,
+    pub branch: Setting<Option<String>>,
+    pub pull_strategy: Setting<PullStrategy>,
+    pub conflict: Setting<ConflictStrategy>,
+    /// the rules of the repository entry followed by the global ones
+    pub conflicts: Vec<ConflictRule>,
+    pub backend: Setting<Backend>,
+    pub timeouts: Setting<Timeouts>,
+}
+
+impl Config {
+    /// loads the configuration with the precedence command line,
+    /// environment, config file and defaults. If `file` is `None` the
+    /// default location is used and a missing file is not an error.
+    pub fn load(cli: &Layer, file: Option<&Path>) -> Result<Config, ConfigError> {
+        let env = Layer::from_env()?;
+        let (path, file) = match file
+        {
+            Some(p) => (Some(p.to_path_buf()), Layer::from_file(p)?),
+            None => match default_path()
+            {
+                Some(ref p) if p.is_file() => (Some(p.clone()), Layer::from_file(p)?),
+                _ => (None, Layer::default()),
+            },
+        };
+        let home = env::var("HOME").unwrap_or_default();
+        Config::layered(path, cli, &env, &file, &home, hostname().as_deref())
+    }
+
+    /// Merges the layers. Without a repository path on the command line
+    /// or in the environment every repository of the config file is
+    /// used, otherwise only the single given one. Values of a repository
+    /// entry take precedence over the global values of the file. `host`
+    /// is the default name, below any configured one.
+    pub fn layered(
+        path: Option<PathBuf>,
+        cli: &Layer,
+        env: &Layer,
+        file: &Layer,
+        home: &str,
+        host: Option<&str>,
+    ) -> Result<Config, ConfigError> {
+        let log_level = pick(
+            &[(Source::Cli, &cli.log_level), (Source::Env, &env.log_level), (Source::File, &file.log_level)],
+            "info".to_string(),
+        );
+        if !LOG_LEVELS.contains(&log_level.value.to_lowercase().as_str())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "unknown log level {} ({})",
+                log_level.value, log_level.source
+            )));
+        }
+        let notify = pick(
+            &[(Source::Cli, &cli.notify), (Source::Env, &env.notify), (Source::File, &file.notify)],
+            false,
+        );
+
+        let single = RepoLayer {
+            path: file.repo.clone(),
+            ..RepoLayer::default()
+        };
+        let rules = |layers: &[&Vec<RuleLayer>]| -> Result<Vec<ConflictRule>, ConfigError> {
+            let mut rules = Vec::new();
+            for r in layers.iter().flat_map(|l| l.iter())
+            {
+                match ConflictRule::new(&r.glob, r.strategy)
+                {
+                    Ok(rule) => rules.push(rule),
+                    Err(e) => return Err(ConfigError::Invalid(format!("glob {}: {}", r.glob, e))),
+                }
+            }
+            Ok(rules)
+        };
+        let entries: Vec<(&str, &RepoLayer)> = if cli.repo.is_some() || env.repo.is_some() || file.repos.is_empty()
+        {
+            vec![("default", &single)]
+        }
+        else
+        {
+            file.repos.iter().map(|(id, r)| (id.as_str(), r)).collect()
+        };
+
+        let mut repos = Vec::new();
+        for (id, entry) in entries
+        {
+            let mut repo_path = pick(
+                &[
+                    (Source::Cli, &cli.repo),
+                    (Source::Env, &env.repo),
+                    (Source::File, &entry.path),
+                ],
+                "~/Shared/sync".to_string(),
+            );
+            repo_path.value = expand_home(&repo_path.value, home);
+            let backend = pick(
+                &[
+                    (Source::Cli, &cli.backend),
+                    (Source::Env, &env.backend),
+                    (Source::File, &entry.backend),
+                    (Source::File, &file.backend),
+                ],
+                Backend::Cli,
+            );
+            if !backend.value.available()
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "gifsy was built without the {} backend ({})",
+                    backend.value, backend.source
+                )));
+            }
+            repos.push(RepoConfig {
+                id: id.to_string(),
+                path: repo_path,
+                name: pick(
+                    &[
+                        (Source::Cli, &cli.name),
+                        (Source::Env, &env.name),
+                        (Source::File, &entry.name),
+                        (Source::File, &file.name),
+                    ],
+                    host.unwrap_or("Unknown Host").to_string(),
+                ),
+                remote: pick(
+                    &[
+                        (Source::Cli, &cli.remote),
+                        (Source::Env, &env.remote),
+                        (Source::File, &entry.remote),
+                        (Source::File, &file.remote),
+                    ],
+                    "origin".to_string(),
+                ),
+                branch: pick(
+                    &[
+                        (Source::Cli, &cli.branch.clone().map(Some)),
+                        (Source::Env, &env.branch.clone().map(Some)),
+                        (Source::File, &entry.branch.clone().map(Some)),
+                        (Source::File, &file.branch.clone().map(Some)),
+                    ],
+                    None,
+                ),
+                pull_strategy: pick(
+                    &[
+                        (Source::Cli, &cli.pull_strategy),
+                        (Source::Env, &env.pull_strategy),
+                        (Source::File, &entry.pull_strategy),
+                        (Source::File, &file.pull_strategy),
+                    ],
+                    PullStrategy::Rebase,
+                ),
+                conflict: pick(
+                    &[
+                        (Source::Cli, &cli.conflict),
+                        (Source::Env, &env.conflict),
+                        (Source::File, &entry.conflict),
+                        (Source::File, &file.conflict),
+                    ],
+                    ConflictStrategy::Manual,
+                ),
+                conflicts: rules(&[&entry.conflicts, &file.conflicts])?,
+                backend,
+                timeouts: timeouts(&entry.timeouts, &file.timeouts)?,
+            });
+        }
+
+        Ok(Config {
+            file: path,
+            notify,
+            log_level,
+            repos,
+        })
+    }
+}
+
+/// the name of the machine from `HOST` or the system
+fn hostname() -> Option<String> {
+    match env::var("HOST")
+    {
+        Ok(h) if !h.is_empty() => return Some(h),
+        _ => (),
+    }
+    let mut buf = [0u8; 256];
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if rc != 0
+    {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8(buf[..len].to_vec()).ok().filter(|h| !h.is_empty())
+}
+
+/// the first value set in `layers` or the default
+fn pick<T: Clone>(layers: &[(Source, &Option<T>)], default: T) -> Setting<T> {
+    layers
+        .iter()
+        .find_map(|&(source, v)| v.clone().map(|value| Setting { value, source }))
+        .unwrap_or(Setting {
+            value: default,
+            source: Source::Default,
+        })
+}
+
+/// the limits of the repository entry, the global ones or the defaults
+fn timeouts(entry: &TimeoutLayer, file: &TimeoutLayer) -> Result<Setting<Timeouts>, ConfigError> {
+    let mut timeouts = Timeouts::default();
+    let mut source = Source::Default;
+    {
+        let limits = [
+            ("fetch", entry.fetch.or(file.fetch), &mut timeouts.fetch),
+            ("pull", entry.pull.or(file.pull), &mut timeouts.pull),
+            ("push", entry.push.or(file.push), &mut timeouts.push),
+            ("submodules", entry.submodules.or(file.submodules), &mut timeouts.submodules),
+            ("local", entry.local.or(file.local), &mut timeouts.local),
+        ];
+        for (key, seconds, limit) in limits
+        {
+            match seconds
+            {
+                Some(0) => return Err(ConfigError::Invalid(format!("timeout {} must be positive", key))),
+                Some(s) =>
+                {
+                    *limit = Duration::from_secs(s);
+                    source = Source::File;
+                }
+                None => (),
+            }
+        }
+    }
+    Ok(Setting {
+        value: timeouts,
+        source,
+    })
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file
+        {
+            Some(ref p) => writeln!(f, "config file: {}", p.display())?,
+            None => writeln!(f, "config file: none")?,
+        }
+        writeln!(f, "notify    = {} ({})", self.notify.value, self.notify.source)?;
+        write!(f, "log_level = {} ({})", self.log_level.value, self.log_level.source)?;
+        for r in &self.repos
+        {
+            write!(f, "\n\n{}", r)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RepoConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "[repos.{}]", self.id)?;
+        writeln!(f, "path      = {} ({})", self.path.value, self.path.source)?;
+        writeln!(f, "name      = {} ({})", self.name.value, self.name.source)?;
+        writeln!(f, "remote    = {} ({})", self.remote.value, self.remote.source)?;
+        write!(
+            f,
+            "branch    = {} ({})",
+            self.branch.value.as_deref().unwrap_or("<current>"),
+            self.branch.source
+        )?;
+        write!(f, "\npull      = {} ({})", self.pull_strategy.value, self.pull_strategy.source)?;
+        write!(f, "\nconflict  = {} ({})", self.conflict.value, self.conflict.source)?;
+        for r in &self.conflicts
+        {
+            write!(f, "\n  {}", r)?;
+        }
+        write!(f, "\nbackend   = {} ({})", self.backend.value, self.backend.source)?;
+        let t = &self.timeouts.value;
+        write!(
+            f,
+            "\ntimeouts  = fetch {}s, pull {}s, push {}s, submodules {}s, local {}s ({})",
+            t.fetch.as_secs(),
+            t.pull.as_secs(),
+            t.push.as_secs(),
+            t.submodules.as_secs(),
+            t.local.as_secs(),
+            self.timeouts.source
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self
+        {
+            ConfigError::Io(ref p, ref e) => write!(f, "can't read {}: {}", p.display(), e),
+            ConfigError::Parse(ref p, ref e) => write!(f, "can't parse {}: {}", p.display(), e),
+            ConfigError::Invalid(ref m) => write!(f, "invalid configuration: {}", m),
+        }
+    }
+}
+impl error::Error for ConfigError {
+}
+
+const LOG_LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+
+/// `$XDG_CONFIG_HOME/gifsy/config.toml` or `~/.config/gifsy/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME")
+    {
+        Some(ref d) if !d.is_empty() => PathBuf::from(d),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("gifsy").join("config.toml"))
+}
+
+fn expand_home(path: &str, home: &str) -> String {
+    if path == "~"
+    {
+        home.to_string()
+    }
+    else if let Some(rest) = path.strip_prefix("~/")
+    {
+        Path::new(home).join(rest).to_string_lossy().into_owned()
+    }
+    else
+    {
+        path.to_string()
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, ConfigError> {
+    match value
+    {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        v => Err(ConfigError::Invalid(format!("{} is not a boolean ({})", v, key))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_precedence() {
+        let cli = Layer {
+            repo: Some("/cli".to_string()),
+            ..Layer::default()
+        };
+        let env = Layer {
+            repo: Some("/env".to_string()),
+            name: Some("envhost".to_string()),
+            ..Layer::default()
+        };
+        let file: Layer = toml::from_str(
+            "repo = \"/file\"\nname = \"filehost\"\nremote = \"backup\"\nnotify = true\n",
+        )
+        .unwrap();
+
+        let c = Config::layered(None, &cli, &env, &file, "/home/me", None).unwrap();
+        assert_eq!(c.repos.len(), 1);
+        let r = &c.repos[0];
+        assert_eq!(r.path.value, "/cli");
+        assert_eq!(r.path.source, Source::Cli);
+        assert_eq!(r.name.value, "envhost");
+        assert_eq!(r.name.source, Source::Env);
+        assert_eq!(r.remote.value, "backup");
+        assert_eq!(r.remote.source, Source::File);
+        assert!(c.notify.value);
+        assert_eq!(r.branch.value, None);
+        assert_eq!(r.branch.source, Source::Default);
+        assert_eq!(c.log_level.value, "info");
+    }
+
+    #[test]
+    fn test_defaults() {
+        let none = Layer::default();
+        let c = Config::layered(None, &none, &none, &none, "/home/me", None).unwrap();
+        let r = &c.repos[0];
+        assert_eq!(r.id, "default");
+        assert_eq!(r.path.value, "/home/me/Shared/sync");
+        assert_eq!(r.path.source, Source::Default);
+        assert_eq!(r.name.value, "Unknown Host");
+        assert_eq!(r.remote.value, "origin");
+        assert!(!c.notify.value);
+    }
+
+    #[test]
+    fn test_backend() {
+        let none = Layer::default();
+        let env = Layer::from_vars(|k| if k == "GIFSY_BACKEND" { Some("cli".to_string()) } else { None }).unwrap();
+        let c = Config::layered(None, &none, &env, &none, "/home/me", None).unwrap();
+        assert_eq!(c.repos[0].backend.value, Backend::Cli);
+        assert_eq!(c.repos[0].backend.source, Source::Env);
+
+        let file: Layer = toml::from_str("backend = \"libgit2\"\n").unwrap();
+        let c = Config::layered(None, &none, &none, &file, "/home/me", None);
+        assert_eq!(c.is_ok(), cfg!(feature = "libgit2"));
+
+        let env = Layer::from_vars(|k| if k == "GIFSY_BACKEND" { Some("jgit".to_string()) } else { None });
+        assert!(env.is_err());
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let none = Layer::default();
+        let file: Layer = toml::from_str(
+            "[timeouts]\npull = 60\npush = 60\n\
+             [repos.notes]\npath = \"/notes\"\n[repos.notes.timeouts]\npush = 30\n",
+        )
+        .unwrap();
+        let c = Config::layered(None, &none, &none, &file, "/home/me", None).unwrap();
+        let t = &c.repos[0].timeouts;
+        assert_eq!(t.source, Source::File);
+        assert_eq!(t.value.pull, Duration::from_secs(60));
+        assert_eq!(t.value.push, Duration::from_secs(30));
+        assert_eq!(t.value.fetch, Timeouts::default().fetch);
+
+        let c = Config::layered(None, &none, &none, &none, "/home/me", None).unwrap();
+        assert_eq!(c.repos[0].timeouts.source, Source::Default);
+
+        let zero: Layer = toml::from_str("[timeouts]\nfetch = 0\n").unwrap();
+        assert!(Config::layered(None, &none, &none, &zero, "/", None).is_err());
+    }
+
+    #[test]
+    fn test_repositories() {
+        let none = Layer::default();
+        let file: Layer = toml::from_str(
+            "name = \"laptop\"\nremote = \"backup\"\n\
+             [repos.notes]\npath = \"~/notes\"\nbranch = \"main\"\npull_strategy = \"ff-only\"\n\
+             [repos.dotfiles]\npath = \"/dots\"\nname = \"dots-laptop\"\n",
+        )
+        .unwrap();
+
+        let c = Config::layered(None, &none, &none, &file, "/home/me", None).unwrap();
+        let ids: Vec<&str> = c.repos.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["dotfiles", "notes"]);
+        assert_eq!(c.repos[0].name.value, "dots-laptop");
+        assert_eq!(c.repos[0].remote.value, "backup");
+        assert_eq!(c.repos[1].path.value, "/home/me/notes");
+        assert_eq!(c.repos[1].name.value, "laptop");
+        assert_eq!(c.repos[1].branch.value, Some("main".to_string()));
+        assert_eq!(c.repos[1].pull_strategy.value, PullStrategy::FfOnly);
+        assert_eq!(c.repos[0].pull_strategy.value, PullStrategy::Rebase);
+
+        let cli = Layer {
+            repo: Some("/other".to_string()),
+            ..Layer::default()
+        };
+        let c = Config::layered(None, &cli, &none, &file, "/home/me", None).unwrap();
+        assert_eq!(c.repos.len(), 1);
+        assert_eq!(c.repos[0].path.value, "/other");
+    }
+
+    #[test]
+    fn test_conflict_rules() {
+        let none = Layer::default();
+        let file: Layer = toml::from_str(
+            "conflict = \"theirs\"\n\
+             [[conflicts]]\nglob = \"*.org\"\nstrategy = \"keep-both\"\n\
+             [repos.notes]\npath = \"/notes\"\n\
+             [[repos.notes.conflicts]]\nglob = \"journal/*\"\nstrategy = \"ours\"\n",
+        )
+        .unwrap();
+
+        let c = Config::layered(None, &none, &none, &file, "/home/me", None).unwrap();
+        let r = &c.repos[0];
+        assert_eq!(r.conflict.value, ConflictStrategy::Theirs);
+        assert_eq!(r.conflicts.len(), 2);
+        assert_eq!(r.conflicts[0].to_string(), "journal/* = ours");
+        assert_eq!(r.conflicts[1].to_string(), "*.org = keep-both");
+
+        let broken: Layer =
+            toml::from_str("[[conflicts]]\nglob = \"[\"\nstrategy = \"ours\"\n").unwrap();
+        assert!(Config::layered(None, &none, &none, &broken, "/", None).is_err());
+    }
+
+    #[test]
+    fn test_host_default() {
+        let none = Layer::default();
+        let env = Layer::from_vars(|k| match k
+        {
+            "HOST" => Some("laptop".to_string()),
+            "GIFSY_NOTIFY" => Some("yes".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(env.name, None);
+        assert_eq!(env.notify, Some(true));
+
+        let c = Config::layered(None, &none, &env, &none, "/", Some("laptop")).unwrap();
+        assert_eq!(c.repos[0].name, Setting { value: "laptop".to_string(), source: Source::Default });
+        let file: Layer = toml::from_str("name = \"desktop\"\n").unwrap();
+        let c = Config::layered(None, &none, &env, &file, "/", Some("laptop")).unwrap();
+        assert_eq!(c.repos[0].name.value, "desktop");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let none = Layer::default();
+        let file: Result<Layer, _> = toml::from_str("colour = \"blue\"\n");
+        assert!(file.is_err());
+        let level = Layer {
+            log_level: Some("loud".to_string()),
+            ..Layer::default()
+        };
+        assert!(Config::layered(None, &none, &none, &level, "/", None).is_err());
+    }
+}
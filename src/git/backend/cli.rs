@@ -0,0 +1,649 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path;
+use std::process::{Command, Output};
+use std::time::Duration;
+
+use super::super::parser::*;
+use super::super::{paths, process};
+use super::super::records::{self, Commit, FileStat, RemoteRef, SubmoduleCommit};
+use super::super::{GifsyError, Interrupted, PullStrategy, Report, Status, Timeouts};
+use super::{GitBackend, Side};
+
+/// runs the git executable in the working tree
+pub struct Cli {
+    path: String,
+    timeouts: Timeouts,
+}
+
+impl Cli {
+    pub fn new(path: &str) -> Cli {
+        Cli {
+            path: path.to_owned(),
+            timeouts: Timeouts::default(),
+        }
+    }
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Cli {
+        self.timeouts = timeouts;
+        self
+    }
+    /// runs git with `args` and `input` on its standard input, `op`
+    /// names it if it exceeds `timeout`
+    fn exec(&self, op: &str, timeout: Duration, args: &[&str], input: Option<Vec<u8>>) -> Result<Output, GifsyError> {
+        let mut cmd = Command::new("git");
+        cmd.current_dir(&self.path).args(args);
+        process::output(cmd, input, op, timeout)
+    }
+    /// runs git talking to a remote, its messages aren't translated to
+    /// recognise an unreachable remote
+    fn remote(&self, op: &str, timeout: Duration, args: &[&str]) -> Result<Output, GifsyError> {
+        let mut cmd = Command::new("git");
+        cmd.current_dir(&self.path).args(args).env("LC_ALL", "C");
+        let output = process::output(cmd, None, op, timeout)?;
+        if !output.status.success()
+        {
+            if let Some(e) = unreachable(&String::from_utf8_lossy(&output.stderr))
+            {
+                return Err(GifsyError::Offline(e));
+            }
+        }
+        Ok(output)
+    }
+    /// runs the local git command `args` with `input` on its standard input
+    fn pipe(&self, args: &[&str], input: Vec<u8>) -> Result<Output, GifsyError> {
+        self.exec(name(args), self.timeouts.local, args, Some(input))
+    }
+    /// runs the local git command `args` and returns its trimmed output
+    fn run(&self, args: &[&str]) -> Result<String, GifsyError> {
+        let out = self.run_raw(args)?;
+        Ok(String::from_utf8_lossy(&out).trim().to_string())
+    }
+    /// runs the local git command `args` and returns its output as is
+    fn run_raw(&self, args: &[&str]) -> Result<Vec<u8>, GifsyError> {
+        checked(self.exec(name(args), self.timeouts.local, args, None)?, args)
+    }
+}
+
+/// the standard output of git run with `args`, fails if git did
+fn checked(output: Output, args: &[&str]) -> Result<Vec<u8>, GifsyError> {
+    if output.status.success()
+    {
+        Ok(output.stdout)
+    }
+    else
+    {
+        Err(GifsyError::CmdFail(
+            output.status.code().unwrap_or(-7),
+            format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr)
+            ),
+        ))
+    }
+}
+
+/// logs the output of `cmd` that failed to parse, the whole output only
+/// in debug as it can be large
+fn unparsable(cmd: &'static str, e: ParseError, output: &[u8]) -> GifsyError {
+    error!("can't parse git {}, {}", cmd, e);
+    debug!("{} output: {:?}", cmd, String::from_utf8_lossy(output));
+    GifsyError::Unparsable(cmd, e)
+}
+
+/// makes `git status` report copies like the libgit2 backend
+const COPIES: &str = "status.renames=copies";
+
+/// the git command of `args`, skipping the global options
+fn name<'a>(args: &[&'a str]) -> &'a str {
+    args.iter()
+        .find(|a| !a.starts_with('-') && !a.contains('='))
+        .cloned()
+        .unwrap_or("git")
+}
+
+/// `files` as input of `--pathspec-file-nul`
+fn nul_separated<P: AsRef<path::Path>>(files: &[P]) -> Vec<u8> {
+    let mut input = Vec::new();
+    for f in files
+    {
+        input.extend_from_slice(paths::as_bytes(f.as_ref()));
+        input.push(0);
+    }
+    input
+}
+
+impl GitBackend for Cli {
+    fn status(&self) -> Result<Vec<Box<Status>>, GifsyError> {
+        let args = ["-c", COPIES, "status", "--porcelain", "-z"];
+        match self.exec("status", self.timeouts.local, &args, None)
+        {
+            Err(e) => Err(e),
+            Ok(output) =>
+            {
+                if output.status.success()
+                {
+                    let p = parsers![parse_flags, parse_from, parse_to];
+                    match parse::<Vec<&Status>>(&output.stdout, p)
+                    {
+                        Err(e) => Err(unparsable("status", e, &output.stdout)),
+                        Ok(status) => Ok(status),
+                    }
+                }
+                else
+                {
+                    Err(GifsyError::CmdFail(
+                        output.status.code().unwrap_or(-1),
+                        String::from_utf8_lossy(&output.stderr).to_string(),
+                    ))
+                }
+            }
+        }
+    }
+    fn report(&self) -> Result<Report, GifsyError> {
+        let out = self.run_raw(&["-c", COPIES, "status", "--porcelain=v2", "--branch", "-z"])?;
+        Report::parse(&out).map_err(|e| unparsable("status", e, &out))
+    }
+    fn add(&self, files: &[&path::Path]) -> Result<(), GifsyError> {
+        let index = self.git_path("index")?;
+        let backup = self.git_path("index.gifsy")?;
+        let saved = match fs::copy(&index, &backup)
+        {
+            Ok(_) => true,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => false,
+            Err(e) => return Err(GifsyError::IoError(e)),
+        };
+        // with --ignore-errors git reports every file it can't add
+        let output = self.pipe(
+            &[
+                "--literal-pathspecs",
+                "add",
+                "--ignore-errors",
+                "--pathspec-from-file=-",
+                "--pathspec-file-nul",
+            ],
+            nul_separated(files),
+        )?;
+        if output.status.success()
+        {
+            if saved
+            {
+                fs::remove_file(&backup).map_err(GifsyError::IoError)?;
+            }
+            return Ok(());
+        }
+        // git wrote the files it could add, put the old index back
+        let restored = if saved { fs::rename(&backup, &index) } else { fs::remove_file(&index) };
+        match restored
+        {
+            Err(ref e) if e.kind() != ErrorKind::NotFound => warn!("can't restore the index ({})", e),
+            _ => debug!("index restored"),
+        }
+        Err(GifsyError::AddFailed(add_failures(&String::from_utf8_lossy(&output.stderr))))
+    }
+    fn remove(&self, files: &[&path::Path]) -> Result<(), GifsyError> {
+        // git rm checks every file before it touches the index
+        let args = [
+            "--literal-pathspecs",
+            "rm",
+            "--quiet",
+            "--pathspec-from-file=-",
+            "--pathspec-file-nul",
+        ];
+        let output = self.pipe(&args, nul_separated(files))?;
+        if output.status.success()
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(GifsyError::CmdFail(
+                output.status.code().unwrap_or(-7),
+                format!("git rm failed: {}", String::from_utf8_lossy(&output.stderr)),
+            ))
+        }
+    }
+    fn checkout(&self, file: &path::Path, side: Side) -> Result<(), GifsyError> {
+        let side = match side
+        {
+            Side::Ours => "--ours",
+            Side::Theirs => "--theirs",
+        };
+        // the file goes through standard input as it needn't be UTF-8
+        let args = [
+            "--literal-pathspecs",
+            "checkout",
+            side,
+            "--pathspec-from-file=-",
+            "--pathspec-file-nul",
+        ];
+        checked(self.pipe(&args, nul_separated(&[file]))?, &args).map(|_| ())
+    }
+    fn show(&self, file: &path::Path, side: Side) -> Result<Vec<u8>, GifsyError> {
+        let stage = match side
+        {
+            Side::Ours => b'2',
+            Side::Theirs => b'3',
+        };
+        let unmerged = self.run_raw(&["ls-files", "--unmerged", "-z"])?;
+        match staged_blob(&unmerged, file, stage)
+        {
+            Some(blob) => self.run_raw(&["cat-file", "blob", &blob]),
+            None => Err(GifsyError::CmdFail(
+                -7,
+                format!("{} has no stage {}", paths::escape(file), stage as char),
+            )),
+        }
+    }
+    fn commit(&self, message: &str) -> Result<String, GifsyError> {
+        let output = self.pipe(&["commit", "--file", "-"], message.as_bytes().to_vec())?;
+        debug!("commit output stdout: {}", String::from_utf8_lossy(&output.stdout));
+        if !output.status.success()
+        {
+            // git tells on stdout that there is nothing to commit
+            let out = if output.stderr.is_empty() { &output.stdout } else { &output.stderr };
+            return Err(GifsyError::CommitFailed(format!(
+                "({}) {}",
+                output.status.code().unwrap_or(-7),
+                String::from_utf8_lossy(out).trim()
+            )));
+        }
+        self.rev_parse("HEAD")
+    }
+    fn fetch(&self, remote: &str, branch: Option<&str>) -> Result<(), GifsyError> {
+        let mut args = vec!["fetch", remote];
+        args.extend(branch);
+        checked(self.remote("fetch", self.timeouts.fetch, &args)?, &args).map(|_| ())
+    }
+    fn pull(&self, remote: &str, branch: Option<&str>, strategy: PullStrategy) -> Result<(), GifsyError> {
+        let mut args = vec!["pull", remote];
+        args.extend(branch);
+        args.push(match strategy
+        {
+            PullStrategy::Rebase => "--rebase",
+            PullStrategy::Merge => "--no-rebase",
+            PullStrategy::FfOnly => "--ff-only",
+        });
+        args.extend(&["--autostash", "--no-edit"]);
+        let output = self.remote("pull", self.timeouts.pull, &args)?;
+
+        debug!(
+            "pull output stdout: {}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        debug!(
+            "pull output stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        debug!("pull status: {}", output.status);
+        if output.status.success()
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(GifsyError::CmdFail(
+                output.status.code().unwrap_or(-1),
+                format!(
+                    "couldn't call git pull: {} err: {}",
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+            ))
+        }
+    }
+    fn push(&self, remote: &str, branch: Option<&str>, set_upstream: bool) -> Result<(), GifsyError> {
+        let mut args = vec!["push", "--porcelain"];
+        args.extend(if set_upstream { Some("--set-upstream") } else { None });
+        args.push(remote);
+        args.extend(branch);
+        let output = self.remote("push", self.timeouts.push, &args)?;
+
+        debug!(
+            "push output stdout: {}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        debug!(
+            "push output stderr: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        debug!("push status: {}", output.status);
+        if let Some(r) = rejected(&String::from_utf8_lossy(&output.stdout))
+        {
+            return Err(GifsyError::Rejected(r));
+        }
+        if output.status.success()
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(GifsyError::CmdFail(
+                output.status.code().unwrap_or(-2),
+                format!(
+                    "couldn't call git push: {} err: {}",
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+            ))
+        }
+    }
+    fn submodules(&self) -> Result<Vec<SubmoduleCommit>, GifsyError> {
+        let out = self.run_raw(&["submodule", "status"])?;
+        SubmoduleCommit::parse_status(&out).map_err(|e| unparsable("submodule status", e, &out))
+    }
+    fn submodules_init(&self) -> Result<(), GifsyError> {
+        let output = self.exec("submodule init", self.timeouts.submodules, &["submodule", "init"], None)?;
+
+        if output.status.success()
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(GifsyError::CmdFail(
+                output.status.code().unwrap_or(-3),
+                format!(
+                    "couldn't call init submodules: {} err: {}",
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+            ))
+        }
+    }
+    fn submodules_update(&self) -> Result<(), GifsyError> {
+        let output = self.remote("submodule update", self.timeouts.submodules, &["submodule", "update"])?;
+
+        if output.status.success()
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(GifsyError::CmdFail(
+                output.status.code().unwrap_or(-4),
+                format!(
+                    "couldn't call update submodules: {} err: {}",
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+            ))
+        }
+    }
+    fn current_branch(&self) -> Result<String, GifsyError> {
+        match self.run(&["symbolic-ref", "--short", "-q", "HEAD"])
+        {
+            Err(GifsyError::CmdFail(1, _)) => Err(GifsyError::DetachedHead),
+            r => r,
+        }
+    }
+    fn upstream(&self) -> Result<Option<String>, GifsyError> {
+        match self.run(&["rev-parse", "--abbrev-ref", "@{upstream}"])
+        {
+            Ok(_) => (),
+            // a missing upstream is reported like a bad revision
+            Err(GifsyError::CmdFail(128, _)) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let head = self.run(&["symbolic-ref", "-q", "HEAD"])?;
+        self.run(&["for-each-ref", "--format=%(upstream:remoteref)", &head])
+            .map(Some)
+    }
+    fn rev_parse(&self, rev: &str) -> Result<String, GifsyError> {
+        self.run(&["rev-parse", rev])
+    }
+    fn is_ancestor(&self, ancestor: &str, commit: &str) -> Result<bool, GifsyError> {
+        match self.run(&["merge-base", "--is-ancestor", ancestor, commit])
+        {
+            Ok(_) => Ok(true),
+            Err(GifsyError::CmdFail(1, _)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+    fn log(&self, range: &str) -> Result<Vec<Commit>, GifsyError> {
+        let out = self.run_raw(&["log", "-z", records::LOG_FORMAT, range])?;
+        Commit::parse_log(&out).map_err(|e| unparsable("log", e, &out))
+    }
+    fn count(&self, rev: &str) -> Result<usize, GifsyError> {
+        // rev-list fails on an unborn branch
+        let verify = self.exec("rev-parse", self.timeouts.local, &["rev-parse", "-q", "--verify", rev], None)?;
+        if !verify.status.success()
+        {
+            return Ok(0);
+        }
+        let out = self.run_raw(&["rev-list", "--count", rev])?;
+        number(&out, b'\n', Field::Count)
+            .map(|(n, _)| n)
+            .map_err(|e| unparsable("rev-list", e, &out))
+    }
+    fn numstat(&self, from: &str, to: &str) -> Result<Vec<FileStat>, GifsyError> {
+        let range = format!("{}...{}", from, to);
+        let out = self.run_raw(&["diff", "--numstat", "-z", "-M", &range])?;
+        FileStat::parse_numstat(&out).map_err(|e| unparsable("diff --numstat", e, &out))
+    }
+    fn ls_remote(&self, remote: &str, patterns: &[&str]) -> Result<Vec<RemoteRef>, GifsyError> {
+        let mut args = vec!["ls-remote", remote];
+        args.extend_from_slice(patterns);
+        let out = checked(self.remote("ls-remote", self.timeouts.fetch, &args)?, &args)?;
+        RemoteRef::parse_ls_remote(&out).map_err(|e| unparsable("ls-remote", e, &out))
+    }
+    fn check_ignore(&self, files: &[path::PathBuf]) -> Result<Vec<path::PathBuf>, GifsyError> {
+        let output = self.pipe(&["check-ignore", "-z", "--stdin"], nul_separated(files))?;
+        // exit code 1 means that none of the files is ignored
+        match output.status.code()
+        {
+            Some(0) => Ok(output
+                .stdout
+                .split(|&b| b == 0)
+                .filter(|f| !f.is_empty())
+                .map(paths::from_bytes)
+                .collect()),
+            Some(1) => Ok(Vec::new()),
+            rc => Err(GifsyError::CmdFail(
+                rc.unwrap_or(-6),
+                format!(
+                    "can't check ignored files: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+            )),
+        }
+    }
+    fn git_path(&self, name: &str) -> Result<path::PathBuf, GifsyError> {
+        let p = self.run(&["rev-parse", "--git-path", name])?;
+        Ok(path::Path::new(&self.path).join(p))
+    }
+    fn stashes(&self) -> Result<Vec<(String, String)>, GifsyError> {
+        Ok(self
+            .run(&["stash", "list", "--format=%gd%x00%gs"])?
+            .lines()
+            .filter_map(|l| l.split_once('\u{0}'))
+            .map(|(stash, subject)| (stash.to_string(), subject.to_string()))
+            .collect())
+    }
+    fn abort(&self, op: &Interrupted) -> Result<(), GifsyError> {
+        match *op
+        {
+            Interrupted::Rebase =>
+            {
+                self.run(&["rebase", "--abort"])?;
+            }
+            Interrupted::Merge =>
+            {
+                self.run(&["merge", "--abort"])?;
+            }
+            Interrupted::Autostash(ref stash) =>
+            {
+                let id = self.run(&["rev-parse", stash])?;
+                self.run(&["reset", "--merge"])?;
+                self.run(&["stash", "drop", stash])?;
+                self.run(&["stash", "store", "-m", "gifsy recover: local changes", &id])?;
+            }
+        }
+        Ok(())
+    }
+    fn resume(&self, op: &Interrupted) -> Result<(), GifsyError> {
+        match *op
+        {
+            Interrupted::Rebase =>
+            {
+                self.run(&["-c", "core.editor=true", "rebase", "--continue"])?;
+            }
+            Interrupted::Merge =>
+            {
+                self.run(&["commit", "--no-edit"])?;
+            }
+            Interrupted::Autostash(ref stash) =>
+            {
+                self.run(&["stash", "drop", stash])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the messages of git and its helpers if a remote can't be reached
+const UNREACHABLE: [&str; 10] = [
+    "Could not resolve host",
+    "Temporary failure in name resolution",
+    "Name or service not known",
+    "unable to look up",
+    "unable to connect to",
+    "Failed to connect to",
+    "Connection refused",
+    "Connection timed out",
+    "Network is unreachable",
+    "No route to host",
+];
+
+/// the line of the untranslated `stderr` of git telling that the remote
+/// is unreachable
+fn unreachable(stderr: &str) -> Option<String> {
+    stderr
+        .lines()
+        .find(|l| UNREACHABLE.iter().any(|u| l.contains(u)))
+        .map(|l| l.trim().to_string())
+}
+
+/// the refs of `git push --porcelain` rejected since the remote moved, a
+/// ref the remote declined itself is no race
+fn rejected(stdout: &str) -> Option<String> {
+    let refs: Vec<String> = stdout
+        .lines()
+        .filter_map(|l| l.strip_prefix("!\t"))
+        .filter_map(|l| l.split_once('\t'))
+        .filter(|(_, summary)| summary.starts_with("[rejected]"))
+        .map(|(r, summary)| format!("{} {}", r, summary.trim_start_matches("[rejected]").trim()))
+        .collect();
+    if refs.is_empty()
+    {
+        None
+    }
+    else
+    {
+        Some(refs.join(", "))
+    }
+}
+
+/// the files and reasons of the failures reported by `git add`
+fn add_failures(stderr: &str) -> Vec<(String, String)> {
+    let mut failures: Vec<(String, String)> = Vec::new();
+    for line in stderr.lines()
+    {
+        let reason = line.split_once(": ").map_or(line, |(_, r)| r);
+        match quoted(line)
+        {
+            Some(f) if !failures.iter().any(|(p, _)| p == f) => failures.push((f.to_string(), reason.to_string())),
+            _ => (),
+        }
+    }
+    if failures.is_empty()
+    {
+        failures.push((String::new(), stderr.trim().to_string()));
+    }
+    failures
+}
+
+/// the object id of `file` in `stage` of the output of
+/// `git ls-files --unmerged -z`, its records are `<mode> <id> <stage>\t<file>`
+fn staged_blob(unmerged: &[u8], file: &path::Path, stage: u8) -> Option<String> {
+    unmerged.split(|&b| b == 0).find_map(|record| {
+        let tab = record.iter().position(|&b| b == b'\t')?;
+        let (info, name) = (&record[..tab], &record[tab + 1..]);
+        let fields: Vec<&[u8]> = info.split(|&b| b == b' ').collect();
+        match fields[..]
+        {
+            [_, id, [s]] if *s == stage && name == paths::as_bytes(file) => Some(String::from_utf8_lossy(id).into_owned()),
+            _ => None,
+        }
+    })
+}
+
+/// the file name in `open("name")` or `'name'` of a git message
+fn quoted(line: &str) -> Option<&str> {
+    if let Some((_, rest)) = line.split_once("(\"")
+    {
+        return rest.rsplit_once("\")").map(|(f, _)| f);
+    }
+    let (_, rest) = line.split_once('\'')?;
+    rest.rsplit_once('\'').map(|(f, _)| f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unreachable() {
+        let ssh = "ssh: Could not resolve hostname example.org: Temporary failure in name resolution\n\
+                   fatal: Could not read from remote repository.\n";
+        assert_eq!(
+            unreachable(ssh),
+            Some("ssh: Could not resolve hostname example.org: Temporary failure in name resolution".to_string())
+        );
+        let https = "fatal: unable to access 'https://example.org/x.git/': Failed to connect to example.org port 443\n";
+        assert!(unreachable(https).is_some());
+        let denied = "git@example.org: Permission denied (publickey).\n\
+                      fatal: Could not read from remote repository.\n";
+        assert_eq!(unreachable(denied), None);
+    }
+
+    #[test]
+    fn test_rejected() {
+        let stdout = "To /tmp/remote.git\n\
+                      !\trefs/heads/main:refs/heads/main\t[rejected] (fetch first)\n\
+                      Done\n";
+        assert_eq!(rejected(stdout), Some("refs/heads/main:refs/heads/main (fetch first)".to_string()));
+        let declined = "!\trefs/heads/main:refs/heads/main\t[remote rejected] (pre-receive hook declined)\n";
+        assert_eq!(rejected(declined), None);
+        assert_eq!(rejected("=\trefs/heads/main:refs/heads/main\t[up to date]\n"), None);
+    }
+
+    #[test]
+    fn test_staged_blob() {
+        let unmerged = b"100644 1111111111111111111111111111111111111111 1\tgr\xfce.txt\0\
+                         100644 2222222222222222222222222222222222222222 2\tgr\xfce.txt\0\
+                         100644 3333333333333333333333333333333333333333 3\tgr\xfce.txt\0";
+        let file = paths::from_bytes(b"gr\xfce.txt");
+        assert_eq!(staged_blob(unmerged, &file, b'3'), Some("3".repeat(40)));
+        assert_eq!(staged_blob(unmerged, path::Path::new("gr.txt"), b'3'), None);
+    }
+
+    #[test]
+    fn test_add_failures() {
+        let stderr = "error: open(\"secret\"): Permission denied\n\
+                      error: unable to index file 'secret'\n\
+                      fatal: pathspec 'it's gone' did not match any files\n\
+                      fatal: adding files failed\n";
+        assert_eq!(
+            add_failures(stderr),
+            vec![
+                ("secret".to_string(), "open(\"secret\"): Permission denied".to_string()),
+                ("it's gone".to_string(), "pathspec 'it's gone' did not match any files".to_string()),
+            ]
+        );
+        assert_eq!(
+            add_failures("fatal: no space left\n"),
+            vec![(String::new(), "fatal: no space left".to_string())]
+        );
+    }
+}
@@ -0,0 +1,1194 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path;
+use std::time::{Duration, Instant};
+
+use git2;
+use git2::build::CheckoutBuilder;
+use git2::{
+    AnnotatedCommit, BranchType, Cred, CredentialType, DiffDelta, Direction, ErrorClass, ErrorCode, FetchOptions,
+    IndexEntry, Oid, Patch, PushOptions, RemoteCallbacks, ResetType, SubmoduleIgnore, SubmoduleStatus,
+    SubmoduleUpdateOptions,
+};
+
+use super::super::parser::{number, tag, Field, ParseError};
+use super::super::{paths, process};
+use super::super::{
+    Branch, Change, Commit, Entry, FileStat, GifsyError, Interrupted, PullStrategy, RemoteRef, Report, Status,
+    StatusKind, Submodule, SubmoduleCommit, SubmoduleState, Timeouts,
+};
+use super::{GitBackend, Side};
+
+/// how often the credentials of a remote are asked for before giving up
+const AUTH_ATTEMPTS: usize = 3;
+
+/// Works on the repository with libgit2. The repository is opened for
+/// every operation as a `git2::Repository` can't be shared between
+/// threads.
+pub struct Libgit2 {
+    path: String,
+    timeouts: Timeouts,
+}
+
+impl Libgit2 {
+    pub fn open(path: &str) -> Result<Libgit2, GifsyError> {
+        git2::Repository::discover(path).map_err(error)?;
+        Ok(Libgit2 {
+            path: path.to_owned(),
+            timeouts: Timeouts::default(),
+        })
+    }
+    /// libgit2 can only stop a network operation while data arrives, a
+    /// stalled connection isn't detected
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Libgit2 {
+        self.timeouts = timeouts;
+        self
+    }
+    fn repo(&self) -> Result<git2::Repository, GifsyError> {
+        git2::Repository::discover(&self.path).map_err(error)
+    }
+    /// the branch headers of `git status --porcelain=v2 --branch`
+    fn branch(&self, repo: &git2::Repository) -> Result<Branch, GifsyError> {
+        let head = match self.current_branch()
+        {
+            Ok(b) => Some(b),
+            Err(GifsyError::DetachedHead) => None,
+            Err(e) => return Err(e),
+        };
+        let oid = match repo.head()
+        {
+            Ok(h) => h.target(),
+            Err(ref e) if e.code() == ErrorCode::UnbornBranch => None,
+            Err(e) => return Err(error(e)),
+        };
+        let upstream = match head
+        {
+            Some(ref b) => match repo.branch_upstream_name(&format!("refs/heads/{}", b))
+            {
+                Ok(name) => name.as_str().map(|n| n.to_string()),
+                Err(ref e) if e.code() == ErrorCode::NotFound => None,
+                Err(e) => return Err(error(e)),
+            },
+            None => None,
+        };
+        // like git there are no counts if the upstream branch is gone
+        let ahead_behind = match (oid, upstream.as_ref().and_then(|u| repo.refname_to_id(u).ok()))
+        {
+            (Some(local), Some(remote)) =>
+            {
+                let (ahead, behind) = repo.graph_ahead_behind(local, remote).map_err(error)?;
+                Some((ahead as u32, behind as u32))
+            }
+            _ => None,
+        };
+        Ok(Branch {
+            oid: oid.map(|o| o.to_string()),
+            head,
+            upstream: upstream.map(|u| u.trim_start_matches("refs/remotes/").to_string()),
+            ahead_behind,
+        })
+    }
+}
+
+impl GitBackend for Libgit2 {
+    fn status(&self) -> Result<Vec<Box<Status>>, GifsyError> {
+        let repo = self.repo()?;
+        let mut opts = git2::StatusOptions::new();
+        opts.include_untracked(true)
+            .recurse_untracked_dirs(false)
+            .renames_head_to_index(true);
+        let statuses = repo.statuses(Some(&mut opts)).map_err(error)?;
+        let unmerged = unmerged(&repo)?;
+        let mut copies = copies(&repo)?;
+        let mut rc = Vec::new();
+        for e in statuses.iter()
+        {
+            let path = paths::from_bytes(e.path_bytes());
+            let s = e.status();
+            let copy = copies.remove(e.path_bytes());
+            let (index, tree) = if s.is_conflicted()
+            {
+                unmerged.get(e.path_bytes()).cloned().unwrap_or(('U', 'U'))
+            }
+            else if copy.is_some()
+            {
+                ('C', tree_flag(s))
+            }
+            else if s.is_wt_new()
+            {
+                ('?', '?')
+            }
+            else
+            {
+                (index_flag(s), tree_flag(s))
+            };
+            let kind = StatusKind::from_flags(index, tree)
+                .ok_or_else(|| GifsyError::Libgit2(format!("unknown status {}{} of {}", index, tree, paths::escape(&path))))?;
+            // the path of an entry is the source of its rename
+            let renamed = e
+                .head_to_index()
+                .filter(|_| s.is_index_renamed())
+                .and_then(|d| d.new_file().path().map(|p| p.to_path_buf()));
+            rc.push(Box::new(match (copy, renamed)
+            {
+                // the path of a copy is its new one
+                (Some(c), _) => Status::renamed(kind, c.path, path),
+                (None, Some(to)) => Status::renamed(kind, path, to),
+                (None, None) => Status::new(kind, path),
+            }));
+        }
+        Ok(rc)
+    }
+    /// libgit2 doesn't tell the similarity of renames and copies, their score is
+    /// `None`
+    fn report(&self) -> Result<Report, GifsyError> {
+        let repo = self.repo()?;
+        let branch = self.branch(&repo)?;
+        let mut opts = git2::StatusOptions::new();
+        opts.include_untracked(true)
+            .recurse_untracked_dirs(false)
+            .renames_head_to_index(true);
+        let statuses = repo.statuses(Some(&mut opts)).map_err(error)?;
+        let mut conflicts = conflicts(&repo)?;
+        let mut copies = copies(&repo)?;
+        let mut entries = Vec::new();
+        let mut untracked = Vec::new();
+        for e in statuses.iter()
+        {
+            let path = paths::from_bytes(e.path_bytes());
+            let s = e.status();
+            if s.is_conflicted()
+            {
+                entries.extend(conflicts.remove(e.path_bytes()));
+                continue;
+            }
+            if s.is_wt_new()
+            {
+                untracked.push(Entry::Untracked(path));
+                continue;
+            }
+            if s.is_ignored()
+            {
+                continue;
+            }
+            let staged = e.head_to_index();
+            let changed = e.index_to_workdir();
+            let index_mode = match (staged.as_ref(), changed.as_ref())
+            {
+                (Some(d), _) => mode(d.new_file().mode()),
+                (None, Some(d)) => mode(d.old_file().mode()),
+                (None, None) => 0,
+            };
+            let head = staged.as_ref().map_or(index_mode, |d| mode(d.old_file().mode()));
+            let tree = changed.as_ref().map_or(index_mode, |d| mode(d.new_file().mode()));
+            let oids = match (staged.as_ref(), changed.as_ref())
+            {
+                (Some(d), _) => [d.old_file().id().to_string(), d.new_file().id().to_string()],
+                (None, Some(d)) => [d.old_file().id().to_string(), d.old_file().id().to_string()],
+                (None, None) => [Oid::zero().to_string(), Oid::zero().to_string()],
+            };
+            let modes = [head, index_mode, tree];
+            let mut change = Change {
+                index: unchanged(index_flag(s)),
+                tree: unchanged(tree_flag(s)),
+                submodule: None,
+                modes,
+                oids,
+                path: path.clone(),
+            };
+            if let Some(c) = copies.remove(e.path_bytes())
+            {
+                // the head side of a copy is its source
+                change.modes[0] = c.mode;
+                change.oids[0] = c.oid.to_string();
+                change.index = 'C';
+                entries.push(Entry::Renamed {
+                    change,
+                    copied: true,
+                    score: None,
+                    from: c.path,
+                });
+                continue;
+            }
+            if modes.contains(&GITLINK)
+            {
+                change.submodule = Some(submodule(&repo, &String::from_utf8_lossy(e.path_bytes()))?);
+            }
+            // the path of an entry is the source of its rename
+            match staged.as_ref().filter(|_| s.is_index_renamed()).and_then(new_path)
+            {
+                Some(to) =>
+                {
+                    change.path = to;
+                    entries.push(Entry::Renamed {
+                        change,
+                        copied: false,
+                        score: None,
+                        from: path,
+                    });
+                }
+                None => entries.push(Entry::Changed(change)),
+            }
+        }
+        entries.extend(untracked);
+        Ok(Report { branch, entries })
+    }
+    fn add(&self, files: &[&path::Path]) -> Result<(), GifsyError> {
+        let repo = self.repo()?;
+        let mut index = repo.index().map_err(error)?;
+        let workdir = workdir(&repo)?;
+        let mut failures = Vec::new();
+        for f in files
+        {
+            // the names are no pathspecs, `*` or `?` in one mustn't stage
+            // other files
+            let added = match workdir.join(f).symlink_metadata()
+            {
+                Ok(ref m) if m.is_dir() => add_dir(&repo, &mut index, f),
+                Ok(_) => index.add_path(f),
+                Err(_) => index.remove_path(f),
+            };
+            if let Err(e) = added
+            {
+                failures.push((paths::escape(f).into_owned(), e.message().to_string()));
+            }
+        }
+        // the index in memory is dropped without being written
+        if !failures.is_empty()
+        {
+            return Err(GifsyError::AddFailed(failures));
+        }
+        index.write().map_err(error)
+    }
+    fn remove(&self, files: &[&path::Path]) -> Result<(), GifsyError> {
+        let repo = self.repo()?;
+        let mut index = repo.index().map_err(error)?;
+        for f in files
+        {
+            index.remove_path(f).map_err(error)?;
+            match fs::remove_file(workdir(&repo)?.join(f))
+            {
+                Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+                r => r.map_err(GifsyError::IoError)?,
+            }
+        }
+        index.write().map_err(error)
+    }
+    fn checkout(&self, file: &path::Path, side: Side) -> Result<(), GifsyError> {
+        let repo = self.repo()?;
+        let mut index = repo.index().map_err(error)?;
+        let mut checkout = CheckoutBuilder::new();
+        checkout.force().path(file);
+        match side
+        {
+            Side::Ours => checkout.use_ours(true),
+            Side::Theirs => checkout.use_theirs(true),
+        };
+        repo.checkout_index(Some(&mut index), Some(&mut checkout)).map_err(error)
+    }
+    fn show(&self, file: &path::Path, side: Side) -> Result<Vec<u8>, GifsyError> {
+        let repo = self.repo()?;
+        let index = repo.index().map_err(error)?;
+        let stage = match side
+        {
+            Side::Ours => 2,
+            Side::Theirs => 3,
+        };
+        match index.get_path(file, stage)
+        {
+            Some(entry) => Ok(repo.find_blob(entry.id).map_err(error)?.content().to_vec()),
+            None => Err(GifsyError::Libgit2(format!("{} has no stage {}", paths::escape(file), stage))),
+        }
+    }
+    fn commit(&self, message: &str) -> Result<String, GifsyError> {
+        let repo = self.repo()?;
+        let mut index = repo.index().map_err(error)?;
+        let tree = index.write_tree().map_err(error)?;
+        let head = match repo.head()
+        {
+            Ok(head) => Some(head.peel_to_commit().map_err(error)?),
+            Err(ref e) if e.code() == ErrorCode::UnbornBranch => None,
+            Err(e) => return Err(error(e)),
+        };
+        if head.as_ref().is_some_and(|h| h.tree_id() == tree)
+        {
+            return Err(GifsyError::CommitFailed("nothing to commit".to_string()));
+        }
+        let tree = repo.find_tree(tree).map_err(error)?;
+        let sig = repo.signature().map_err(error)?;
+        let parents: Vec<&git2::Commit> = head.iter().collect();
+        repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents)
+            .map(|id| id.to_string())
+            .map_err(error)
+    }
+    fn fetch(&self, remote: &str, branch: Option<&str>) -> Result<(), GifsyError> {
+        fetch(&self.repo()?, remote, branch, Deadline::new("fetch", self.timeouts.fetch)?)
+    }
+    fn pull(&self, remote: &str, branch: Option<&str>, strategy: PullStrategy) -> Result<(), GifsyError> {
+        let mut repo = self.repo()?;
+        fetch(&repo, remote, branch, Deadline::new("pull", self.timeouts.pull)?)?;
+        let upstream = match branch
+        {
+            Some(b) => format!("refs/remotes/{}/{}", remote, b),
+            None =>
+            {
+                let head = format!("refs/heads/{}", self.current_branch()?);
+                let name = repo.branch_upstream_name(&head).map_err(error)?;
+                name.as_str().unwrap_or_default().to_string()
+            }
+        };
+        let theirs = repo.refname_to_id(&upstream).map_err(error)?;
+        let (analysis, _) = {
+            let commit = repo.find_annotated_commit(theirs).map_err(error)?;
+            repo.merge_analysis(&[&commit]).map_err(error)?
+        };
+        if analysis.is_up_to_date()
+        {
+            return Ok(());
+        }
+        let fast_forward = analysis.is_fast_forward() || analysis.is_unborn();
+        if !fast_forward && strategy == PullStrategy::FfOnly
+        {
+            return Err(GifsyError::Diverged);
+        }
+        let stash = autostash(&mut repo)?;
+        let rc = {
+            let commit = repo.find_reference(&upstream).and_then(|r| repo.reference_to_annotated_commit(&r));
+            match commit
+            {
+                Err(e) => Err(error(e)),
+                Ok(_) if fast_forward => forward(&repo, theirs),
+                Ok(ref c) if strategy == PullStrategy::Rebase => rebase(&repo, c),
+                Ok(ref c) => merge(&repo, c),
+            }
+        };
+        let rc = match rc
+        {
+            Ok(()) if !fast_forward && strategy == PullStrategy::Merge => commit_merge(&mut repo),
+            rc => rc,
+        };
+        match stash
+        {
+            Some(stash) if rc.is_ok() => unstash(&mut repo, stash),
+            Some(stash) =>
+            {
+                store(&mut repo, stash, "autostash")?;
+                rc
+            }
+            None => rc,
+        }
+    }
+    fn push(&self, remote: &str, branch: Option<&str>, set_upstream: bool) -> Result<(), GifsyError> {
+        let repo = self.repo()?;
+        let current = self.current_branch()?;
+        let local = format!("refs/heads/{}", current);
+        let target = match branch
+        {
+            Some(b) => format!("refs/heads/{}", b),
+            None => self.upstream()?.unwrap_or_else(|| local.clone()),
+        };
+        let mut r = find_remote(&repo, remote)?;
+        let mut rejected = None;
+        {
+            let deadline = Deadline::new("push", self.timeouts.push)?;
+            let mut callbacks = callbacks(&repo, deadline)?;
+            callbacks.push_update_reference(|name, status| {
+                rejected = match status
+                {
+                    Some(s) if s.contains("non-fast-forward") || s.contains("fetch first") =>
+                    {
+                        Some(GifsyError::Rejected(format!("{} ({})", name, s)))
+                    }
+                    Some(s) => Some(GifsyError::Libgit2(format!("{} rejected ({})", name, s))),
+                    None => None,
+                };
+                Ok(())
+            });
+            let mut opts = PushOptions::new();
+            opts.remote_callbacks(callbacks);
+            match r.push(&[format!("{}:{}", local, target)], Some(&mut opts))
+            {
+                // libgit2 refuses to push what the remote doesn't have
+                Err(ref e) if e.code() == ErrorCode::NotFastForward =>
+                {
+                    return Err(GifsyError::Rejected(e.message().to_string()))
+                }
+                r => r.map_err(|e| deadline.error(e))?,
+            }
+        }
+        if let Some(r) = rejected
+        {
+            return Err(r);
+        }
+        if set_upstream
+        {
+            let upstream = format!("{}/{}", remote, target.trim_start_matches("refs/heads/"));
+            repo.find_branch(&current, BranchType::Local)
+                .and_then(|mut b| b.set_upstream(Some(&upstream)))
+                .map_err(error)?;
+        }
+        Ok(())
+    }
+    fn submodules(&self) -> Result<Vec<SubmoduleCommit>, GifsyError> {
+        let repo = self.repo()?;
+        let mut rc = Vec::new();
+        for sm in repo.submodules().map_err(error)?
+        {
+            let status = repo
+                .submodule_status(sm.name().unwrap_or_default(), SubmoduleIgnore::None)
+                .map_err(error)?;
+            let (state, id) = if status.contains(SubmoduleStatus::WD_UNINITIALIZED)
+            {
+                (SubmoduleState::Uninitialized, sm.index_id())
+            }
+            else if status.contains(SubmoduleStatus::WD_MODIFIED)
+            {
+                (SubmoduleState::Modified, sm.workdir_id())
+            }
+            else
+            {
+                (SubmoduleState::Current, sm.index_id())
+            };
+            rc.push(SubmoduleCommit {
+                state,
+                oid: id.unwrap_or_else(Oid::zero).to_string(),
+                path: sm.path().to_path_buf(),
+                describe: None,
+            });
+        }
+        Ok(rc)
+    }
+    fn submodules_init(&self) -> Result<(), GifsyError> {
+        let repo = self.repo()?;
+        for mut sm in repo.submodules().map_err(error)?
+        {
+            sm.init(false).map_err(error)?;
+        }
+        Ok(())
+    }
+    fn submodules_update(&self) -> Result<(), GifsyError> {
+        let repo = self.repo()?;
+        let deadline = Deadline::new("submodule update", self.timeouts.submodules)?;
+        for mut sm in repo.submodules().map_err(error)?
+        {
+            let status = repo
+                .submodule_status(sm.name().unwrap_or_default(), SubmoduleIgnore::None)
+                .map_err(error)?;
+            // like git only initialized submodules get updated
+            if !status.contains(SubmoduleStatus::IN_CONFIG)
+            {
+                continue;
+            }
+            let mut fetch = FetchOptions::new();
+            fetch.remote_callbacks(callbacks(&repo, deadline)?);
+            let mut opts = SubmoduleUpdateOptions::new();
+            opts.fetch(fetch);
+            sm.update(false, Some(&mut opts)).map_err(|e| deadline.error(e))?;
+        }
+        Ok(())
+    }
+    fn current_branch(&self) -> Result<String, GifsyError> {
+        let repo = self.repo()?;
+        let head = repo.find_reference("HEAD").map_err(error)?;
+        match head.symbolic_target()
+        {
+            Some(target) => Ok(target.trim_start_matches("refs/heads/").to_string()),
+            None => Err(GifsyError::DetachedHead),
+        }
+    }
+    fn upstream(&self) -> Result<Option<String>, GifsyError> {
+        let current = match self.current_branch()
+        {
+            Ok(b) => b,
+            Err(GifsyError::DetachedHead) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let repo = self.repo()?;
+        match repo.branch_upstream_name(&format!("refs/heads/{}", current))
+        {
+            Ok(_) => (),
+            Err(ref e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(error(e)),
+        }
+        let config = repo.config().map_err(error)?;
+        config
+            .get_string(&format!("branch.{}.merge", current))
+            .map(Some)
+            .map_err(error)
+    }
+    fn rev_parse(&self, rev: &str) -> Result<String, GifsyError> {
+        let repo = self.repo()?;
+        let object = repo.revparse_single(rev).map_err(error)?;
+        Ok(object.id().to_string())
+    }
+    fn is_ancestor(&self, ancestor: &str, commit: &str) -> Result<bool, GifsyError> {
+        let repo = self.repo()?;
+        let ancestor = repo.revparse_single(ancestor).map_err(error)?.id();
+        let commit = repo.revparse_single(commit).map_err(error)?.id();
+        Ok(ancestor == commit || repo.graph_descendant_of(commit, ancestor).map_err(error)?)
+    }
+    fn log(&self, range: &str) -> Result<Vec<Commit>, GifsyError> {
+        let repo = self.repo()?;
+        let mut walk = repo.revwalk().map_err(error)?;
+        walk.set_sorting(git2::Sort::TIME).map_err(error)?;
+        walk.push_range(range).map_err(error)?;
+        let mut rc = Vec::new();
+        for id in walk
+        {
+            let commit = repo.find_commit(id.map_err(error)?).map_err(error)?;
+            let short = commit.as_object().short_id().map_err(error)?;
+            let author = commit.author();
+            rc.push(Commit {
+                id: commit.id().to_string(),
+                short_id: short.as_str().unwrap_or_default().to_string(),
+                author: String::from_utf8_lossy(author.name_bytes()).into_owned(),
+                time: author.when().seconds(),
+                subject: String::from_utf8_lossy(commit.summary_bytes().unwrap_or_default()).into_owned(),
+            });
+        }
+        Ok(rc)
+    }
+    fn count(&self, rev: &str) -> Result<usize, GifsyError> {
+        let repo = self.repo()?;
+        let mut walk = repo.revwalk().map_err(error)?;
+        match repo.revparse_single(rev)
+        {
+            Ok(o) => walk.push(o.id()).map_err(error)?,
+            Err(ref e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => return Ok(0),
+            Err(e) => return Err(error(e)),
+        }
+        Ok(walk.count())
+    }
+    fn numstat(&self, from: &str, to: &str) -> Result<Vec<FileStat>, GifsyError> {
+        let repo = self.repo()?;
+        let from = repo.revparse_single(from).map_err(error)?.peel_to_commit().map_err(error)?;
+        let to = repo.revparse_single(to).map_err(error)?.peel_to_commit().map_err(error)?;
+        let base = repo.merge_base(from.id(), to.id()).map_err(error)?;
+        let base = repo.find_commit(base).map_err(error)?.tree().map_err(error)?;
+        let mut diff = repo
+            .diff_tree_to_tree(Some(&base), Some(&to.tree().map_err(error)?), None)
+            .map_err(error)?;
+        diff.find_similar(None).map_err(error)?;
+        let mut rc = Vec::new();
+        for (i, d) in diff.deltas().enumerate()
+        {
+            // the lines of a binary file aren't counted
+            let (added, deleted) = match Patch::from_diff(&diff, i).map_err(error)?
+            {
+                Some(ref p) if !p.delta().flags().is_binary() =>
+                {
+                    let (_, added, deleted) = p.line_stats().map_err(error)?;
+                    (Some(added as u32), Some(deleted as u32))
+                }
+                _ => (None, None),
+            };
+            let moved = d.status() == git2::Delta::Renamed || d.status() == git2::Delta::Copied;
+            rc.push(FileStat {
+                added,
+                deleted,
+                path: paths::from_bytes(d.new_file().path_bytes().unwrap_or_default()),
+                from: d
+                    .old_file()
+                    .path_bytes()
+                    .filter(|_| moved)
+                    .map(paths::from_bytes),
+            });
+        }
+        Ok(rc)
+    }
+    fn ls_remote(&self, remote: &str, patterns: &[&str]) -> Result<Vec<RemoteRef>, GifsyError> {
+        let repo = self.repo()?;
+        let mut r = find_remote(&repo, remote)?;
+        let deadline = Deadline::new("ls-remote", self.timeouts.fetch)?;
+        let connection = r
+            .connect_auth(Direction::Fetch, Some(callbacks(&repo, deadline)?), None)
+            .map_err(|e| deadline.error(e))?;
+        Ok(connection
+            .list()
+            .map_err(error)?
+            .iter()
+            .filter(|h| patterns.is_empty() || patterns.iter().any(|p| matches_ref(h.name(), p)))
+            .map(|h| RemoteRef {
+                oid: h.oid().to_string(),
+                name: h.name().to_string(),
+            })
+            .collect())
+    }
+    fn check_ignore(&self, files: &[path::PathBuf]) -> Result<Vec<path::PathBuf>, GifsyError> {
+        let repo = self.repo()?;
+        let mut rc = Vec::new();
+        for f in files
+        {
+            if repo.is_path_ignored(f).map_err(error)?
+            {
+                rc.push(f.clone());
+            }
+        }
+        Ok(rc)
+    }
+    fn git_path(&self, name: &str) -> Result<path::PathBuf, GifsyError> {
+        Ok(self.repo()?.path().join(name))
+    }
+    fn stashes(&self) -> Result<Vec<(String, String)>, GifsyError> {
+        let mut repo = self.repo()?;
+        let mut rc = Vec::new();
+        repo.stash_foreach(|i, message, _| {
+            rc.push((format!("stash@{{{}}}", i), message.to_string()));
+            true
+        })
+        .map_err(error)?;
+        Ok(rc)
+    }
+    fn abort(&self, op: &Interrupted) -> Result<(), GifsyError> {
+        let mut repo = self.repo()?;
+        match *op
+        {
+            Interrupted::Rebase =>
+            {
+                let mut rebase = repo.open_rebase(None).map_err(error)?;
+                rebase.abort().map_err(error)?;
+                drop(rebase);
+                pop_autostash(&mut repo)?;
+            }
+            Interrupted::Merge =>
+            {
+                reset(&repo)?;
+                repo.cleanup_state().map_err(error)?;
+                pop_autostash(&mut repo)?;
+            }
+            Interrupted::Autostash(ref stash) =>
+            {
+                let id = repo.revparse_single(stash).map_err(error)?.id();
+                reset(&repo)?;
+                repo.stash_drop(stash_index(stash)?).map_err(error)?;
+                store(&mut repo, id, "gifsy recover: local changes")?;
+            }
+        }
+        Ok(())
+    }
+    fn resume(&self, op: &Interrupted) -> Result<(), GifsyError> {
+        let mut repo = self.repo()?;
+        match *op
+        {
+            Interrupted::Rebase =>
+            {
+                let sig = repo.signature().map_err(error)?;
+                let mut rebase = repo.open_rebase(None).map_err(error)?;
+                if rebase.operation_current().is_some()
+                {
+                    commit_step(&repo, &mut rebase, &sig)?;
+                }
+                finish_rebase(&repo, &mut rebase, &sig)?;
+                drop(rebase);
+                pop_autostash(&mut repo)?;
+            }
+            Interrupted::Merge =>
+            {
+                commit_merge(&mut repo)?;
+                pop_autostash(&mut repo)?;
+            }
+            Interrupted::Autostash(ref stash) =>
+            {
+                repo.stash_drop(stash_index(stash)?).map_err(error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn error(e: git2::Error) -> GifsyError {
+    GifsyError::Libgit2(e.message().to_string())
+}
+
+/// `name` ends with the components of `pattern` like `git ls-remote`
+/// matches refs
+fn matches_ref(name: &str, pattern: &str) -> bool {
+    match name.strip_suffix(pattern)
+    {
+        Some(head) => head.is_empty() || head.ends_with('/'),
+        None => false,
+    }
+}
+
+/// stage a directory like `git add <dir>`, the files in it that aren't
+/// ignored and the deletions of tracked ones
+fn add_dir(repo: &git2::Repository, index: &mut git2::Index, dir: &path::Path) -> Result<(), git2::Error> {
+    let workdir = repo.workdir().unwrap_or_else(|| path::Path::new(""));
+    let io = |e: std::io::Error| git2::Error::from_str(&e.to_string());
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(d) = pending.pop()
+    {
+        for entry in fs::read_dir(workdir.join(&d)).map_err(io)?
+        {
+            let entry = entry.map_err(io)?;
+            let file = d.join(entry.file_name());
+            if entry.file_name() == ".git" || repo.status_should_ignore(&file)?
+            {
+                continue;
+            }
+            match entry.file_type()
+            {
+                // a nested repository isn't part of this one
+                Ok(ref t) if t.is_dir() && workdir.join(&file).join(".git").exists() => (),
+                Ok(ref t) if t.is_dir() => pending.push(file),
+                _ => index.add_path(&file)?,
+            }
+        }
+    }
+    let deleted: Vec<_> = index
+        .iter()
+        .map(|e| paths::from_bytes(&e.path))
+        .filter(|p| p.starts_with(dir) && workdir.join(p).symlink_metadata().is_err())
+        .collect();
+    for p in deleted
+    {
+        index.remove_path(&p)?;
+    }
+    Ok(())
+}
+
+fn workdir(repo: &git2::Repository) -> Result<&path::Path, GifsyError> {
+    repo.workdir()
+        .ok_or_else(|| GifsyError::Libgit2("bare repository".to_string()))
+}
+
+fn index_flag(s: git2::Status) -> char {
+    if s.is_index_new()
+    {
+        'A'
+    }
+    else if s.is_index_deleted()
+    {
+        'D'
+    }
+    else if s.is_index_renamed()
+    {
+        'R'
+    }
+    else if s.is_index_typechange()
+    {
+        'T'
+    }
+    else if s.is_index_modified()
+    {
+        'M'
+    }
+    else
+    {
+        ' '
+    }
+}
+
+fn tree_flag(s: git2::Status) -> char {
+    if s.is_wt_deleted()
+    {
+        'D'
+    }
+    else if s.is_wt_typechange()
+    {
+        'T'
+    }
+    else if s.is_wt_modified() || s.is_wt_renamed()
+    {
+        'M'
+    }
+    else
+    {
+        ' '
+    }
+}
+
+/// the mode of submodules in trees and the index
+const GITLINK: u32 = 0o160000;
+
+fn mode(m: git2::FileMode) -> u32 {
+    i32::from(m) as u32
+}
+
+/// git's `.` for an unchanged side
+fn unchanged(flag: char) -> char {
+    if flag == ' ' { '.' } else { flag }
+}
+
+/// the source of a copy
+struct Source {
+    path: path::PathBuf,
+    mode: u32,
+    oid: Oid,
+}
+
+/// the staged copies by their new path, libgit2's status doesn't look
+/// for them like `status.renames=copies` makes git do
+fn copies(repo: &git2::Repository) -> Result<HashMap<Vec<u8>, Source>, GifsyError> {
+    let head = repo.head().ok().and_then(|h| h.peel_to_tree().ok());
+    let mut diff = repo.diff_tree_to_index(head.as_ref(), None, None).map_err(error)?;
+    let mut opts = git2::DiffFindOptions::new();
+    opts.renames(true).copies(true);
+    diff.find_similar(Some(&mut opts)).map_err(error)?;
+    Ok(diff
+        .deltas()
+        .filter(|d| d.status() == git2::Delta::Copied)
+        .filter_map(|d| {
+            let from = d.old_file();
+            let source = Source {
+                path: paths::from_bytes(from.path_bytes()?),
+                mode: mode(from.mode()),
+                oid: from.id(),
+            };
+            Some((d.new_file().path_bytes()?.to_vec(), source))
+        })
+        .collect())
+}
+
+fn new_path(d: &DiffDelta<'_>) -> Option<path::PathBuf> {
+    d.new_file().path_bytes().map(paths::from_bytes)
+}
+
+fn submodule(repo: &git2::Repository, path: &str) -> Result<Submodule, GifsyError> {
+    let status = repo.submodule_status(path, SubmoduleIgnore::None).map_err(error)?;
+    Ok(Submodule {
+        commit_changed: status.contains(SubmoduleStatus::WD_MODIFIED),
+        modified: status.intersects(SubmoduleStatus::WD_INDEX_MODIFIED | SubmoduleStatus::WD_WD_MODIFIED),
+        untracked: status.contains(SubmoduleStatus::WD_UNTRACKED),
+    })
+}
+
+/// the unmerged entries of the report by their paths
+fn conflicts(repo: &git2::Repository) -> Result<HashMap<Vec<u8>, Entry>, GifsyError> {
+    let flags = unmerged(repo)?;
+    let index = repo.index().map_err(error)?;
+    let mut rc = HashMap::new();
+    for c in index.conflicts().map_err(error)?
+    {
+        let c = c.map_err(error)?;
+        let bytes = match c.our.as_ref().or(c.their.as_ref()).or(c.ancestor.as_ref())
+        {
+            Some(e) => e.path.clone(),
+            None => continue,
+        };
+        let stage = |e: &Option<IndexEntry>| e.as_ref().map_or((0, Oid::zero()), |e| (e.mode, e.id));
+        let (base, ours, theirs) = (stage(&c.ancestor), stage(&c.our), stage(&c.their));
+        let tree = match fs::symlink_metadata(workdir(repo)?.join(paths::from_bytes(&bytes)))
+        {
+            Ok(m) if m.file_type().is_symlink() => 0o120000,
+            Ok(m) if m.is_dir() => GITLINK,
+            Ok(m) if std::os::unix::fs::PermissionsExt::mode(&m.permissions()) & 0o111 != 0 => 0o100755,
+            Ok(_) => 0o100644,
+            Err(_) => 0,
+        };
+        let (index, tree_flag) = flags.get(&bytes).cloned().unwrap_or(('U', 'U'));
+        let modes = [base.0, ours.0, theirs.0, tree];
+        let submodule = if modes.contains(&GITLINK)
+        {
+            Some(submodule(repo, &String::from_utf8_lossy(&bytes))?)
+        }
+        else
+        {
+            None
+        };
+        let path = paths::from_bytes(&bytes);
+        rc.insert(
+            bytes,
+            Entry::Unmerged {
+                index,
+                tree: tree_flag,
+                submodule,
+                modes,
+                oids: [base.1.to_string(), ours.1.to_string(), theirs.1.to_string()],
+                path,
+            },
+        );
+    }
+    Ok(rc)
+}
+
+/// the two letter status of the unmerged files by their paths, by which
+/// sides have the file
+fn unmerged(repo: &git2::Repository) -> Result<HashMap<Vec<u8>, (char, char)>, GifsyError> {
+    let index = repo.index().map_err(error)?;
+    let mut rc = HashMap::new();
+    for c in index.conflicts().map_err(error)?
+    {
+        let c = c.map_err(error)?;
+        let flags = match (c.ancestor.is_some(), c.our.is_some(), c.their.is_some())
+        {
+            (_, false, true) if c.ancestor.is_some() => ('D', 'U'),
+            (_, true, false) if c.ancestor.is_some() => ('U', 'D'),
+            (false, true, true) => ('A', 'A'),
+            (false, true, false) => ('A', 'U'),
+            (false, false, true) => ('U', 'A'),
+            _ => ('U', 'U'),
+        };
+        let entry = c.our.or(c.their).or(c.ancestor);
+        if let Some(e) = entry
+        {
+            rc.insert(e.path, flags);
+        }
+    }
+    Ok(rc)
+}
+
+/// the end of a network operation, libgit2 stops it at the next progress
+/// report after that
+#[derive(Clone, Copy)]
+struct Deadline {
+    op: &'static str,
+    at: Instant,
+}
+
+impl Deadline {
+    fn new(op: &'static str, timeout: Duration) -> Result<Deadline, GifsyError> {
+        if process::cancelled()
+        {
+            return Err(GifsyError::Cancelled);
+        }
+        Ok(Deadline {
+            op,
+            at: Instant::now() + timeout,
+        })
+    }
+    fn passed(&self) -> bool {
+        process::cancelled() || Instant::now() >= self.at
+    }
+    /// the error of an operation that failed or was stopped
+    fn error(&self, e: git2::Error) -> GifsyError {
+        if process::cancelled()
+        {
+            GifsyError::Cancelled
+        }
+        else if Instant::now() >= self.at
+        {
+            GifsyError::Timeout(self.op.to_string())
+        }
+        // resolving and connecting fail as network errors
+        else if e.class() == ErrorClass::Net
+        {
+            GifsyError::Offline(e.message().to_string())
+        }
+        else
+        {
+            error(e)
+        }
+    }
+}
+
+fn callbacks<'a>(repo: &git2::Repository, deadline: Deadline) -> Result<RemoteCallbacks<'a>, GifsyError> {
+    let config = repo.config().map_err(error)?;
+    let mut attempts = 0;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.transfer_progress(move |_| !deadline.passed());
+    callbacks.sideband_progress(move |_| !deadline.passed());
+    callbacks.credentials(move |url, user, allowed| {
+        if deadline.passed()
+        {
+            return Err(git2::Error::from_str("stopped"));
+        }
+        attempts += 1;
+        if attempts > AUTH_ATTEMPTS
+        {
+            return Err(git2::Error::from_str("authentication failed"));
+        }
+        if allowed.contains(CredentialType::SSH_KEY)
+        {
+            Cred::ssh_key_from_agent(user.unwrap_or("git"))
+        }
+        else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT)
+        {
+            Cred::credential_helper(&config, url, user)
+        }
+        else
+        {
+            Cred::default()
+        }
+    });
+    Ok(callbacks)
+}
+
+/// the named remote or an anonymous one for an url
+fn find_remote<'r>(repo: &'r git2::Repository, remote: &str) -> Result<git2::Remote<'r>, GifsyError> {
+    repo.find_remote(remote)
+        .or_else(|_| repo.remote_anonymous(remote))
+        .map_err(error)
+}
+
+fn fetch(repo: &git2::Repository, remote: &str, branch: Option<&str>, deadline: Deadline) -> Result<(), GifsyError> {
+    let mut r = find_remote(repo, remote)?;
+    let refspecs: Vec<String> = branch
+        .map(|b| format!("+refs/heads/{0}:refs/remotes/{1}/{0}", b, remote))
+        .into_iter()
+        .collect();
+    let mut opts = FetchOptions::new();
+    opts.remote_callbacks(callbacks(repo, deadline)?);
+    r.fetch(&refspecs, Some(&mut opts), None)
+        .map_err(|e| deadline.error(e))
+}
+
+/// stashes the local changes, `None` if there are none
+fn autostash(repo: &mut git2::Repository) -> Result<Option<Oid>, GifsyError> {
+    let sig = repo.signature().map_err(error)?;
+    match repo.stash_save(&sig, "autostash", None)
+    {
+        Ok(id) => Ok(Some(id)),
+        Err(ref e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(error(e)),
+    }
+}
+
+/// Applies the autostash again like git does. Conflicts are left in the
+/// working tree and the stash is kept as `autostash` until they are
+/// resolved.
+fn unstash(repo: &mut git2::Repository, stash: Oid) -> Result<(), GifsyError> {
+    store(repo, stash, "autostash")?;
+    let mut checkout = CheckoutBuilder::new();
+    checkout.allow_conflicts(true);
+    let mut opts = git2::StashApplyOptions::new();
+    opts.checkout_options(checkout);
+    repo.stash_apply(0, Some(&mut opts)).map_err(error)?;
+    if repo.index().map_err(error)?.has_conflicts()
+    {
+        warn!("applying the autostash resulted in conflicts");
+        return Ok(());
+    }
+    repo.stash_drop(0).map_err(error)
+}
+
+/// applies the autostash an interrupted pull kept once the rebase or
+/// merge is done
+fn pop_autostash(repo: &mut git2::Repository) -> Result<(), GifsyError> {
+    let mut autostash = None;
+    repo.stash_foreach(|_, message, id| {
+        if message == "autostash"
+        {
+            autostash = Some(*id);
+        }
+        autostash.is_none()
+    })
+    .map_err(error)?;
+    match autostash
+    {
+        Some(id) => unstash(repo, id),
+        None => Ok(()),
+    }
+}
+
+/// Puts the stash `id` on top of the stash list with `message` as its
+/// subject. A previous entry of the same stash is removed.
+fn store(repo: &mut git2::Repository, id: Oid, message: &str) -> Result<(), GifsyError> {
+    let mut index = None;
+    repo.stash_foreach(|i, _, stash| {
+        if *stash == id
+        {
+            index = Some(i);
+        }
+        index.is_none()
+    })
+    .map_err(error)?;
+    if let Some(i) = index
+    {
+        repo.stash_drop(i).map_err(error)?;
+    }
+    repo.reference_ensure_log("refs/stash").map_err(error)?;
+    repo.reference("refs/stash", id, true, message)
+        .map(|_| ())
+        .map_err(error)
+}
+
+/// the index of a stash entry named like `stash@{1}`
+fn stash_index(stash: &str) -> Result<usize, GifsyError> {
+    let parsed = tag(stash.as_bytes(), b"stash@{", Field::Ref).and_then(|((), s)| number(s, b'}', Field::Ref));
+    match parsed
+    {
+        Ok((i, [])) => Ok(i),
+        Ok((_, rest)) => Err(ParseError::at(Field::Ref, rest)),
+        Err(e) => Err(e),
+    }
+    .map_err(|e| GifsyError::Unparsable("stash list", e.within(stash.as_bytes())))
+}
+
+fn reset(repo: &git2::Repository) -> Result<(), GifsyError> {
+    let head = repo.head().and_then(|h| h.peel(git2::ObjectType::Commit)).map_err(error)?;
+    repo.reset(&head, ResetType::Hard, None).map_err(error)
+}
+
+/// moves the current branch to `id`
+fn forward(repo: &git2::Repository, id: Oid) -> Result<(), GifsyError> {
+    let head = repo.find_reference("HEAD").map_err(error)?;
+    let branch = match head.symbolic_target()
+    {
+        Some(b) => b.to_string(),
+        None => return Err(GifsyError::DetachedHead),
+    };
+    let target = repo.find_object(id, None).map_err(error)?;
+    repo.checkout_tree(&target, Some(CheckoutBuilder::new().safe()))
+        .map_err(error)?;
+    repo.reference(&branch, id, true, "pull: fast-forward")
+        .map(|_| ())
+        .map_err(error)
+}
+
+fn rebase(repo: &git2::Repository, upstream: &AnnotatedCommit) -> Result<(), GifsyError> {
+    let sig = repo.signature().map_err(error)?;
+    let mut rebase = repo.rebase(None, Some(upstream), None, None).map_err(error)?;
+    finish_rebase(repo, &mut rebase, &sig)
+}
+
+/// applies the remaining commits of the rebase, stops at a conflict
+fn finish_rebase(repo: &git2::Repository, rebase: &mut git2::Rebase, sig: &git2::Signature) -> Result<(), GifsyError> {
+    while let Some(op) = rebase.next()
+    {
+        op.map_err(error)?;
+        commit_step(repo, rebase, sig)?;
+    }
+    rebase.finish(Some(sig)).map_err(error)
+}
+
+fn commit_step(repo: &git2::Repository, rebase: &mut git2::Rebase, sig: &git2::Signature) -> Result<(), GifsyError> {
+    if repo.index().map_err(error)?.has_conflicts()
+    {
+        return Err(GifsyError::Libgit2("conflicts while rebasing".to_string()));
+    }
+    match rebase.commit(None, sig, None)
+    {
+        // the change is upstream already
+        Err(ref e) if e.code() == ErrorCode::Applied => Ok(()),
+        r => r.map(|_| ()).map_err(error),
+    }
+}
+
+fn merge(repo: &git2::Repository, theirs: &AnnotatedCommit) -> Result<(), GifsyError> {
+    repo.merge(&[theirs], None, None).map_err(error)?;
+    if repo.index().map_err(error)?.has_conflicts()
+    {
+        return Err(GifsyError::Libgit2("conflicts while merging".to_string()));
+    }
+    Ok(())
+}
+
+/// commits the merge in progress
+fn commit_merge(repo: &mut git2::Repository) -> Result<(), GifsyError> {
+    let mut heads = Vec::new();
+    repo.mergehead_foreach(|id| {
+        heads.push(*id);
+        true
+    })
+    .map_err(error)?;
+    // git2 panics on a message naming a file that isn't UTF-8
+    let message = fs::read(repo.path().join("MERGE_MSG")).map_err(GifsyError::IoError)?;
+    let message = String::from_utf8_lossy(&message);
+    let sig = repo.signature().map_err(error)?;
+    let mut parents = vec![repo.head().and_then(|h| h.peel_to_commit()).map_err(error)?];
+    for id in heads
+    {
+        parents.push(repo.find_commit(id).map_err(error)?);
+    }
+    let parents: Vec<&git2::Commit> = parents.iter().collect();
+    let tree = repo
+        .index()
+        .and_then(|mut i| i.write_tree())
+        .and_then(|t| repo.find_tree(t))
+        .map_err(error)?;
+    repo.commit(Some("HEAD"), &sig, &sig, &message, &tree, &parents)
+        .map_err(error)?;
+    repo.cleanup_state().map_err(error)
+}
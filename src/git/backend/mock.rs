@@ -0,0 +1,391 @@
+use std::env;
+use std::fs;
+use std::path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::super::parser::*;
+use super::super::paths;
+use super::super::{
+    Branch, Change, ChangeKind, Commit, Entry, FileStat, GifsyError, Interrupted, PullStrategy, RemoteRef, Report,
+    Status, StatusKind, SubmoduleCommit,
+};
+use super::{GitBackend, Side};
+
+static MOCKS: AtomicUsize = AtomicUsize::new(0);
+
+/// An in-memory `GitBackend` for tests. It keeps a status, records the
+/// operations changing the repository and fails the operations scripted
+/// with `fail`. Clones share their state, so a test keeps one to inspect
+/// the backend it gave to a `Repository`.
+#[derive(Clone)]
+pub struct Mock {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    /// stands in for the git directory, it holds the lock file and the
+    /// marker of an interrupted rebase
+    dir: path::PathBuf,
+    status: Vec<Status>,
+    calls: Vec<String>,
+    failures: Vec<(&'static str, GifsyError)>,
+    conflict: bool,
+    branch: Option<String>,
+    upstream: bool,
+    unpushed: usize,
+    /// submodules with only untracked content, `add` stages nothing of
+    /// them
+    dirty_submodules: Vec<path::PathBuf>,
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Mock {
+    /// a clean repository on branch `main` with an upstream branch
+    pub fn new() -> Mock {
+        let dir = env::temp_dir().join(format!(
+            "gifsy-mock-{}-{}",
+            process::id(),
+            MOCKS.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir).expect("can't create mock git directory");
+        Mock {
+            state: Arc::new(Mutex::new(State {
+                dir,
+                status: Vec::new(),
+                calls: Vec::new(),
+                failures: Vec::new(),
+                conflict: false,
+                branch: Some("main".to_string()),
+                upstream: true,
+                unpushed: 0,
+                dirty_submodules: Vec::new(),
+            })),
+        }
+    }
+    /// sets the status from the output of `git status --porcelain -z`
+    pub fn with_status(self, porcelain: &str) -> Mock {
+        let p = parsers![parse_flags, parse_from, parse_to];
+        let status = parse::<Vec<&Status>>(porcelain.as_bytes(), p).expect("invalid porcelain status");
+        self.state().status = status.into_iter().map(|s| *s).collect();
+        self
+    }
+    /// `file` is a submodule with untracked content, it's listed as
+    /// modified but can't be staged
+    pub fn with_dirty_submodule(self, file: &str) -> Mock {
+        {
+            let mut state = self.state();
+            state.status.push(Status::new(
+                StatusKind::Tracked {
+                    index: ChangeKind::Unmodified,
+                    tree: ChangeKind::Modified,
+                },
+                path::PathBuf::from(file),
+            ));
+            state.dirty_submodules.push(path::PathBuf::from(file));
+        }
+        self
+    }
+    /// checks out `branch`, `None` detaches HEAD
+    pub fn with_branch(self, branch: Option<&str>) -> Mock {
+        self.state().branch = branch.map(|b| b.to_string());
+        self
+    }
+    /// the branch has neither an upstream nor a remote branch yet
+    pub fn without_upstream(self) -> Mock {
+        self.state().upstream = false;
+        self
+    }
+    /// the next call of the backend method `op` fails with `e`
+    pub fn fail(&self, op: &'static str, e: GifsyError) {
+        self.state().failures.push((op, e));
+    }
+    /// the next pull stops at a conflict and leaves a rebase behind
+    pub fn conflict(&self) {
+        self.state().conflict = true;
+    }
+    /// the operations that changed the repository in the order of their
+    /// calls
+    pub fn calls(&self) -> Vec<String> {
+        self.state().calls.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+    /// records `call` and fails if a failure of `op` is scripted
+    fn call(&self, op: &'static str, call: Option<String>) -> Result<(), GifsyError> {
+        let mut state = self.state();
+        state.calls.extend(call);
+        match state.failures.iter().position(|&(o, _)| o == op)
+        {
+            Some(i) => Err(state.failures.remove(i).1),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for Mock {
+    fn default() -> Mock {
+        Mock::new()
+    }
+}
+
+impl State {
+    fn rebase(&self) -> path::PathBuf {
+        self.dir.join("rebase-merge")
+    }
+}
+
+impl GitBackend for Mock {
+    fn status(&self) -> Result<Vec<Box<Status>>, GifsyError> {
+        self.call("status", None)?;
+        let state = self.state();
+        Ok(state.status.iter().cloned().map(Box::new).collect())
+    }
+    fn report(&self) -> Result<Report, GifsyError> {
+        self.call("report", None)?;
+        let state = self.state();
+        let upstream = state.branch.as_ref().filter(|_| state.upstream);
+        let branch = Branch {
+            oid: Some(format!("{:040x}", state.unpushed)),
+            head: state.branch.clone(),
+            upstream: upstream.map(|b| format!("origin/{}", b)),
+            ahead_behind: upstream.map(|_| (state.unpushed as u32, 0)),
+        };
+        let entries = state.status.iter().map(entry).collect();
+        Ok(Report { branch, entries })
+    }
+    fn add(&self, files: &[&path::Path]) -> Result<(), GifsyError> {
+        self.call("add", Some(format!("add {}", joined(files))))?;
+        let mut state = self.state();
+        let dirty = state.dirty_submodules.clone();
+        for s in state
+            .status
+            .iter_mut()
+            .filter(|s| files.contains(&s.file()) && !dirty.iter().any(|d| d == s.file()))
+        {
+            let index = match s.kind
+            {
+                StatusKind::Untracked | StatusKind::Ignored => ChangeKind::Added,
+                StatusKind::Unmerged { .. } => ChangeKind::Modified,
+                StatusKind::Tracked {
+                    index: ChangeKind::Unmodified,
+                    tree,
+                } => tree,
+                StatusKind::Tracked { index, .. } => index,
+            };
+            s.kind = StatusKind::Tracked {
+                index,
+                tree: ChangeKind::Unmodified,
+            };
+        }
+        Ok(())
+    }
+    fn remove(&self, files: &[&path::Path]) -> Result<(), GifsyError> {
+        self.call("remove", Some(format!("rm {}", joined(files))))?;
+        let mut state = self.state();
+        for s in state.status.iter_mut().filter(|s| files.contains(&s.file()))
+        {
+            s.kind = StatusKind::Tracked {
+                index: ChangeKind::Deleted,
+                tree: ChangeKind::Unmodified,
+            };
+        }
+        Ok(())
+    }
+    fn checkout(&self, file: &path::Path, side: Side) -> Result<(), GifsyError> {
+        self.call("checkout", Some(format!("checkout {:?} {}", side, paths::escape(file))))
+    }
+    fn show(&self, _file: &path::Path, side: Side) -> Result<Vec<u8>, GifsyError> {
+        self.call("show", None)?;
+        Ok(format!("{:?}\n", side).into_bytes())
+    }
+    fn commit(&self, _message: &str) -> Result<String, GifsyError> {
+        self.call("commit", Some("commit".to_string()))?;
+        let mut state = self.state();
+        if state.status.iter().all(|s| s.index() == ChangeKind::Unmodified)
+        {
+            return Err(GifsyError::CommitFailed("nothing to commit".to_string()));
+        }
+        state.status.retain(|s| s.index() == ChangeKind::Unmodified);
+        state.unpushed += 1;
+        Ok(format!("{:040x}", state.unpushed))
+    }
+    fn fetch(&self, remote: &str, _branch: Option<&str>) -> Result<(), GifsyError> {
+        self.call("fetch", Some(format!("fetch {}", remote)))
+    }
+    fn pull(&self, remote: &str, _branch: Option<&str>, strategy: PullStrategy) -> Result<(), GifsyError> {
+        self.call("pull", Some(format!("pull {} {}", remote, strategy)))?;
+        let mut state = self.state();
+        if state.conflict
+        {
+            state.conflict = false;
+            fs::create_dir_all(state.rebase()).map_err(GifsyError::IoError)?;
+            return Err(GifsyError::CmdFail(1, "conflict".to_string()));
+        }
+        Ok(())
+    }
+    fn push(&self, remote: &str, _branch: Option<&str>, set_upstream: bool) -> Result<(), GifsyError> {
+        let call = if set_upstream
+        {
+            format!("push --set-upstream {}", remote)
+        }
+        else
+        {
+            format!("push {}", remote)
+        };
+        self.call("push", Some(call))?;
+        let mut state = self.state();
+        state.unpushed = 0;
+        state.upstream = true;
+        Ok(())
+    }
+    fn submodules(&self) -> Result<Vec<SubmoduleCommit>, GifsyError> {
+        self.call("submodules", None)?;
+        Ok(Vec::new())
+    }
+    fn submodules_init(&self) -> Result<(), GifsyError> {
+        self.call("submodules_init", Some("submodule init".to_string()))
+    }
+    fn submodules_update(&self) -> Result<(), GifsyError> {
+        self.call("submodules_update", Some("submodule update".to_string()))
+    }
+    fn current_branch(&self) -> Result<String, GifsyError> {
+        self.call("current_branch", None)?;
+        let state = self.state();
+        state.branch.clone().ok_or(GifsyError::DetachedHead)
+    }
+    fn upstream(&self) -> Result<Option<String>, GifsyError> {
+        self.call("upstream", None)?;
+        let state = self.state();
+        Ok(match state.branch
+        {
+            Some(ref b) if state.upstream => Some(format!("refs/heads/{}", b)),
+            _ => None,
+        })
+    }
+    fn rev_parse(&self, rev: &str) -> Result<String, GifsyError> {
+        self.call("rev_parse", None)?;
+        Ok(rev.to_string())
+    }
+    fn is_ancestor(&self, _ancestor: &str, _commit: &str) -> Result<bool, GifsyError> {
+        self.call("is_ancestor", None)?;
+        Ok(true)
+    }
+    fn log(&self, range: &str) -> Result<Vec<Commit>, GifsyError> {
+        self.call("log", None)?;
+        let state = self.state();
+        if !range.ends_with("..HEAD")
+        {
+            return Ok(Vec::new());
+        }
+        Ok((0..state.unpushed)
+            .rev()
+            .map(|i| Commit {
+                id: format!("{:040x}", i),
+                short_id: format!("{:07x}", i),
+                author: "mock".to_string(),
+                time: 0,
+                subject: format!("commit {}", i),
+            })
+            .collect())
+    }
+    fn count(&self, _rev: &str) -> Result<usize, GifsyError> {
+        self.call("count", None)?;
+        Ok(self.state().unpushed)
+    }
+    fn numstat(&self, _from: &str, _to: &str) -> Result<Vec<FileStat>, GifsyError> {
+        self.call("numstat", None)?;
+        Ok(Vec::new())
+    }
+    fn ls_remote(&self, _remote: &str, patterns: &[&str]) -> Result<Vec<RemoteRef>, GifsyError> {
+        self.call("ls_remote", None)?;
+        let state = self.state();
+        if !state.upstream
+        {
+            return Ok(Vec::new());
+        }
+        // the name stands in for the object id
+        Ok(patterns
+            .iter()
+            .map(|p| RemoteRef {
+                oid: p.to_string(),
+                name: p.to_string(),
+            })
+            .collect())
+    }
+    fn check_ignore(&self, _files: &[path::PathBuf]) -> Result<Vec<path::PathBuf>, GifsyError> {
+        self.call("check_ignore", None)?;
+        Ok(Vec::new())
+    }
+    fn git_path(&self, name: &str) -> Result<path::PathBuf, GifsyError> {
+        self.call("git_path", None)?;
+        let state = self.state();
+        Ok(state.dir.join(name))
+    }
+    fn stashes(&self) -> Result<Vec<(String, String)>, GifsyError> {
+        self.call("stashes", None)?;
+        Ok(Vec::new())
+    }
+    fn abort(&self, op: &Interrupted) -> Result<(), GifsyError> {
+        self.call("abort", Some(format!("abort {}", op)))?;
+        let state = self.state();
+        fs::remove_dir_all(state.rebase()).map_err(GifsyError::IoError)
+    }
+    fn resume(&self, op: &Interrupted) -> Result<(), GifsyError> {
+        self.call("resume", Some(format!("resume {}", op)))?;
+        let state = self.state();
+        fs::remove_dir_all(state.rebase()).map_err(GifsyError::IoError)
+    }
+}
+
+fn joined(files: &[&path::Path]) -> String {
+    let files: Vec<_> = files.iter().map(|f| paths::escape(f)).collect();
+    files.join(" ")
+}
+
+/// the porcelain v2 record of a porcelain v1 status
+fn entry(s: &Status) -> Entry {
+    let flag = |f: char| if f == ' ' { '.' } else { f };
+    let (index, tree) = s.kind.flags();
+    let zero = format!("{:040x}", 0);
+    let file = s.file().to_path_buf();
+    let change = Change {
+        index: flag(index),
+        tree: flag(tree),
+        submodule: None,
+        modes: [0o100644; 3],
+        oids: [zero.clone(), zero.clone()],
+        path: file.clone(),
+    };
+    match s.kind
+    {
+        StatusKind::Untracked => Entry::Untracked(file),
+        StatusKind::Ignored => Entry::Ignored(file),
+        StatusKind::Unmerged { .. } => Entry::Unmerged {
+            index,
+            tree,
+            submodule: None,
+            modes: [0o100644; 4],
+            oids: [zero.clone(), zero.clone(), zero],
+            path: file,
+        },
+        _ => match s.source()
+        {
+            Some(from) => Entry::Renamed {
+                change,
+                copied: s.index() == ChangeKind::Copied,
+                score: Some(100),
+                from: from.to_path_buf(),
+            },
+            None => Entry::Changed(change),
+        },
+    }
+}
@@ -0,0 +1,335 @@
+use std::fmt;
+use std::path;
+use std::str;
+
+use super::{
+    Commit, FileStat, GifsyError, Interrupted, PullStrategy, RemoteRef, Report, Status, SubmoduleCommit, Timeouts,
+};
+
+pub use self::cli::Cli;
+#[cfg(feature = "libgit2")]
+pub use self::libgit2::Libgit2;
+#[cfg(test)]
+pub use self::mock::Mock;
+
+pub mod cli;
+#[cfg(feature = "libgit2")]
+pub mod libgit2;
+#[cfg(test)]
+pub mod mock;
+
+/// a side of an unmerged file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+impl Side {
+    pub fn other(self) -> Side {
+        match self
+        {
+            Side::Ours => Side::Theirs,
+            Side::Theirs => Side::Ours,
+        }
+    }
+}
+
+/// The primitive git operations of a repository. `Repository` builds
+/// syncing, locking and conflict handling on top of them.
+pub trait GitBackend: Send + Sync {
+    /// the changes of the index and the working tree
+    fn status(&self) -> Result<Vec<Box<Status>>, GifsyError>;
+    /// the changes with the branch headers, the file modes and the states
+    /// of the submodules
+    fn report(&self) -> Result<Report, GifsyError>;
+    /// stages `files` at once, if one of them fails nothing is staged and
+    /// every failing file is reported by `GifsyError::AddFailed`
+    fn add(&self, files: &[&path::Path]) -> Result<(), GifsyError>;
+    /// removes `files` from the index and the working tree
+    fn remove(&self, files: &[&path::Path]) -> Result<(), GifsyError>;
+    /// replaces an unmerged file in the working tree with one side
+    fn checkout(&self, file: &path::Path, side: Side) -> Result<(), GifsyError>;
+    /// the content of one side of an unmerged file
+    fn show(&self, file: &path::Path, side: Side) -> Result<Vec<u8>, GifsyError>;
+    /// commits the index and returns the id of the new commit, fails with
+    /// `GifsyError::CommitFailed` if there is nothing to commit
+    fn commit(&self, message: &str) -> Result<String, GifsyError>;
+    fn fetch(&self, remote: &str, branch: Option<&str>) -> Result<(), GifsyError>;
+    /// fetches `branch` or the upstream branch and integrates it, local
+    /// changes are stashed meanwhile
+    fn pull(&self, remote: &str, branch: Option<&str>, strategy: PullStrategy) -> Result<(), GifsyError>;
+    /// pushes the current branch to `branch` or its upstream branch
+    fn push(&self, remote: &str, branch: Option<&str>, set_upstream: bool) -> Result<(), GifsyError>;
+    fn submodules(&self) -> Result<Vec<SubmoduleCommit>, GifsyError>;
+    fn submodules_init(&self) -> Result<(), GifsyError>;
+    fn submodules_update(&self) -> Result<(), GifsyError>;
+    /// the checked out branch, fails with `GifsyError::DetachedHead`
+    fn current_branch(&self) -> Result<String, GifsyError>;
+    /// the name of the upstream branch on the remote, `None` if the
+    /// current branch has none
+    fn upstream(&self) -> Result<Option<String>, GifsyError>;
+    /// the object id of `rev`
+    fn rev_parse(&self, rev: &str) -> Result<String, GifsyError>;
+    /// true if `ancestor` is reachable from `commit`
+    fn is_ancestor(&self, ancestor: &str, commit: &str) -> Result<bool, GifsyError>;
+    /// the commits in `range`, newest first
+    fn log(&self, range: &str) -> Result<Vec<Commit>, GifsyError>;
+    /// the number of commits reachable from `rev`, 0 if it doesn't exist
+    /// like `HEAD` on an unborn branch
+    fn count(&self, rev: &str) -> Result<usize, GifsyError>;
+    /// the files `to` changed since it forked from `from`
+    fn numstat(&self, from: &str, to: &str) -> Result<Vec<FileStat>, GifsyError>;
+    /// the refs of the remote matching one of `patterns` like
+    /// `git ls-remote` does, all of them without patterns
+    fn ls_remote(&self, remote: &str, patterns: &[&str]) -> Result<Vec<RemoteRef>, GifsyError>;
+    /// the subset of `files` that is ignored
+    fn check_ignore(&self, files: &[path::PathBuf]) -> Result<Vec<path::PathBuf>, GifsyError>;
+    /// the path of `name` inside the git directory
+    fn git_path(&self, name: &str) -> Result<path::PathBuf, GifsyError>;
+    /// name and subject of the stash entries, newest first
+    fn stashes(&self) -> Result<Vec<(String, String)>, GifsyError>;
+    /// throws away the interrupted operation, an autostash is kept as a
+    /// regular stash entry
+    fn abort(&self, op: &Interrupted) -> Result<(), GifsyError>;
+    /// finishes the interrupted operation once nothing is unmerged
+    fn resume(&self, op: &Interrupted) -> Result<(), GifsyError>;
+}
+
+/// the implementations of `GitBackend` to choose from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// runs the git executable
+    Cli,
+    /// links libgit2, needs the `libgit2` feature
+    Libgit2,
+}
+
+impl Backend {
+    /// true if the backend is compiled in
+    pub fn available(&self) -> bool {
+        match *self
+        {
+            Backend::Cli => true,
+            Backend::Libgit2 => cfg!(feature = "libgit2"),
+        }
+    }
+
+    pub fn open(&self, path: &str, timeouts: Timeouts) -> Result<Box<dyn GitBackend>, GifsyError> {
+        match *self
+        {
+            Backend::Cli => Ok(Box::new(Cli::new(path).with_timeouts(timeouts))),
+            #[cfg(feature = "libgit2")]
+            Backend::Libgit2 => Ok(Box::new(Libgit2::open(path)?.with_timeouts(timeouts))),
+            #[cfg(not(feature = "libgit2"))]
+            Backend::Libgit2 => Err(GifsyError::Unsupported("libgit2 backend")),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self
+        {
+            Backend::Cli => write!(f, "cli"),
+            Backend::Libgit2 => write!(f, "libgit2"),
+        }
+    }
+}
+
+impl str::FromStr for Backend {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Backend, String> {
+        match s
+        {
+            "cli" => Ok(Backend::Cli),
+            "libgit2" => Ok(Backend::Libgit2),
+            s => Err(format!("unknown backend {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process::Command;
+
+    use super::super::Repository;
+    use super::*;
+
+    /// a fresh repository in the temporary directory
+    fn scratch(name: &str) -> path::PathBuf {
+        let dir = env::temp_dir().join(format!("gifsy-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        git(&dir, &["init", "-q", "-b", "main"]);
+        git(&dir, &["config", "user.name", "test"]);
+        git(&dir, &["config", "user.email", "test@example.org"]);
+        dir
+    }
+
+    fn git(dir: &path::Path, args: &[&str]) {
+        let status = Command::new("git").current_dir(dir).args(args).status().unwrap();
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    /// the backends of this build
+    fn backends() -> Vec<Backend> {
+        vec![Backend::Cli, Backend::Libgit2].into_iter().filter(|b| b.available()).collect()
+    }
+
+    /// a repository with `f.txt` and `g.txt` and a clone of it
+    fn cloned(name: &str) -> (path::PathBuf, path::PathBuf) {
+        let origin = scratch(&format!("origin-{}", name));
+        fs::write(origin.join("f.txt"), "base").unwrap();
+        fs::write(origin.join("g.txt"), "base").unwrap();
+        git(&origin, &["add", "."]);
+        git(&origin, &["commit", "-qm", "base"]);
+        let dir = env::temp_dir().join(format!("gifsy-clone-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        git(&origin, &["clone", "-q", ".", dir.to_str().unwrap()]);
+        git(&dir, &["config", "user.name", "test"]);
+        git(&dir, &["config", "user.email", "test@example.org"]);
+        (origin, dir)
+    }
+
+    fn entries(backend: &dyn GitBackend) -> Vec<String> {
+        backend.report().unwrap().entries.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn test_add_literal_names() {
+        for b in backends()
+        {
+            let dir = scratch(&format!("add-{}", b));
+            fs::write(dir.join("ab.txt"), "1").unwrap();
+            git(&dir, &["add", "ab.txt"]);
+            git(&dir, &["commit", "-qm", "init"]);
+            fs::write(dir.join("ab.txt"), "2").unwrap();
+            fs::write(dir.join("a?.txt"), "new").unwrap();
+            for d in &["d*", "dx"]
+            {
+                fs::create_dir(dir.join(d)).unwrap();
+                fs::write(dir.join(d).join("x.txt"), "new").unwrap();
+            }
+            let backend = b.open(dir.to_str().unwrap(), Timeouts::default()).unwrap();
+            backend.add(&[path::Path::new("a?.txt"), path::Path::new("d*/")]).unwrap();
+            assert_eq!(
+                entries(&*backend),
+                ["A  a?.txt", " M ab.txt", "A  d*/x.txt", "?? dx/"],
+                "{} backend",
+                b
+            );
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_copies_and_typechanges() {
+        for b in backends()
+        {
+            let dir = scratch(&format!("kinds-{}", b));
+            let lines: Vec<String> = (1..50).map(|i| i.to_string()).collect();
+            fs::write(dir.join("a.txt"), lines.join("\n")).unwrap();
+            fs::write(dir.join("f"), "file").unwrap();
+            fs::write(dir.join("g"), "file").unwrap();
+            git(&dir, &["add", "."]);
+            git(&dir, &["commit", "-qm", "init"]);
+            fs::write(dir.join("a.txt"), lines.join("\n") + "\n50").unwrap();
+            fs::copy(dir.join("a.txt"), dir.join("c.txt")).unwrap();
+            for f in &["f", "g"]
+            {
+                fs::remove_file(dir.join(f)).unwrap();
+                std::os::unix::fs::symlink("a.txt", dir.join(f)).unwrap();
+            }
+            git(&dir, &["add", "a.txt", "c.txt", "g"]);
+            let backend = b.open(dir.to_str().unwrap(), Timeouts::default()).unwrap();
+            assert_eq!(
+                entries(&*backend),
+                ["M  a.txt", "C  a.txt -> c.txt", " T f", "T  g"],
+                "{} backend",
+                b
+            );
+            let status: Vec<_> = backend
+                .status()
+                .unwrap()
+                .iter()
+                .map(|s| (s.kind().flags(), s.source().map(|p| p.to_path_buf()), s.file().to_path_buf()))
+                .collect();
+            assert_eq!(status[1], (('C', ' '), Some("a.txt".into()), "c.txt".into()), "{} backend", b);
+            assert_eq!(status[2].0, (' ', 'T'), "{} backend", b);
+            assert_eq!(status[3].0, ('T', ' '), "{} backend", b);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_count() {
+        for b in backends()
+        {
+            let dir = scratch(&format!("count-{}", b));
+            let backend = b.open(dir.to_str().unwrap(), Timeouts::default()).unwrap();
+            assert_eq!(backend.count("HEAD").unwrap(), 0, "{} backend", b);
+            git(&dir, &["commit", "-q", "--allow-empty", "-m", "one"]);
+            git(&dir, &["commit", "-q", "--allow-empty", "-m", "two"]);
+            assert_eq!(backend.count("HEAD").unwrap(), 2, "{} backend", b);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_recover_autostash() {
+        for b in backends()
+        {
+            let (origin, dir) = cloned(&format!("rebase-{}", b));
+            fs::write(dir.join("f.txt"), "local").unwrap();
+            git(&dir, &["commit", "-qam", "local"]);
+            fs::write(dir.join("g.txt"), "dirty").unwrap();
+            fs::write(origin.join("f.txt"), "remote").unwrap();
+            git(&origin, &["commit", "-qam", "remote"]);
+
+            let backend = b.open(dir.to_str().unwrap(), Timeouts::default()).unwrap();
+            let repo = Repository::from(dir.to_str().unwrap(), "test").unwrap().with_backend(backend);
+            assert!(repo.pull().is_err(), "{} backend", b);
+            assert_eq!(repo.interrupted().unwrap(), Some(Interrupted::Rebase), "{} backend", b);
+            fs::write(dir.join("f.txt"), "resolved").unwrap();
+            git(&dir, &["add", "f.txt"]);
+            assert_eq!(repo.recover_continue().unwrap(), Some(Interrupted::Rebase), "{} backend", b);
+            assert_eq!(repo.interrupted().unwrap(), None, "{} backend", b);
+            assert_eq!(fs::read_to_string(dir.join("g.txt")).unwrap(), "dirty", "{} backend", b);
+            assert_eq!(fs::read_to_string(dir.join("f.txt")).unwrap(), "resolved", "{} backend", b);
+            fs::remove_dir_all(&dir).unwrap();
+            fs::remove_dir_all(&origin).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_autostash_conflict() {
+        for b in backends()
+        {
+            let (origin, dir) = cloned(&format!("autostash-{}", b));
+            fs::write(dir.join("f.txt"), "local").unwrap();
+            fs::write(origin.join("f.txt"), "remote").unwrap();
+            git(&origin, &["commit", "-qam", "remote"]);
+
+            let backend = b.open(dir.to_str().unwrap(), Timeouts::default()).unwrap();
+            let repo = Repository::from(dir.to_str().unwrap(), "test").unwrap().with_backend(backend);
+            repo.pull().unwrap();
+            match repo.interrupted().unwrap()
+            {
+                Some(Interrupted::Autostash(_)) => (),
+                i => panic!("{} backend left {:?}", b, i),
+            }
+            assert!(fs::read_to_string(dir.join("f.txt")).unwrap().contains("local"), "{} backend", b);
+            fs::write(dir.join("f.txt"), "resolved").unwrap();
+            git(&dir, &["add", "f.txt"]);
+            repo.recover_continue().unwrap();
+            assert_eq!(repo.interrupted().unwrap(), None, "{} backend", b);
+            assert_eq!(fs::read_to_string(dir.join("f.txt")).unwrap(), "resolved", "{} backend", b);
+            fs::remove_dir_all(&dir).unwrap();
+            fs::remove_dir_all(&origin).unwrap();
+        }
+    }
+}
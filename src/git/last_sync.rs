@@ -0,0 +1,85 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use chrono::prelude::*;
+use toml;
+
+use super::{GifsyError, Synced};
+
+/// how a synchronization ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Done,
+    /// committed, but the remote was unreachable
+    Offline,
+    Failed,
+}
+
+/// The time and the outcome of the last synchronization, kept in the git
+/// directory so `gifsy status` can tell how healthy the repository is.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LastSync {
+    /// seconds since the epoch
+    pub time: i64,
+    pub outcome: Outcome,
+    /// the error of a failed or the unpushed commits of an offline
+    /// synchronization
+    #[serde(default)]
+    pub message: String,
+}
+
+impl LastSync {
+    pub fn new(result: &Result<Synced, GifsyError>) -> LastSync {
+        let (outcome, message) = match *result
+        {
+            Ok(Synced::Done) => (Outcome::Done, String::new()),
+            Ok(Synced::Offline(n)) => (Outcome::Offline, format!("{} commits not pushed yet", n)),
+            Err(ref e) => (Outcome::Failed, e.to_string()),
+        };
+        LastSync {
+            time: Utc::now().timestamp(),
+            outcome,
+            message,
+        }
+    }
+    /// `None` if gifsy never synchronized the repository
+    pub fn read(file: &Path) -> Result<Option<LastSync>, GifsyError> {
+        match fs::read_to_string(file)
+        {
+            Ok(s) => toml::from_str(&s)
+                .map(Some)
+                .map_err(|e| invalid(format!("{} {}", file.display(), e.message()))),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(GifsyError::IoError(e)),
+        }
+    }
+    pub fn write(&self, file: &Path) -> Result<(), GifsyError> {
+        let s = toml::to_string(self).map_err(|e| invalid(e.to_string()))?;
+        fs::write(file, s).map_err(GifsyError::IoError)
+    }
+}
+
+/// a file that isn't a valid last synchronization
+fn invalid(message: String) -> GifsyError {
+    GifsyError::IoError(io::Error::new(ErrorKind::InvalidData, message))
+}
+
+impl fmt::Display for LastSync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match Local.timestamp_opt(self.time, 0).single()
+        {
+            Some(t) => write!(f, "{} ", t.format("%Y-%m-%d %H:%M:%S"))?,
+            None => write!(f, "at {}s ", self.time)?,
+        }
+        match self.outcome
+        {
+            Outcome::Done => write!(f, "done"),
+            Outcome::Offline => write!(f, "offline, {}", self.message),
+            Outcome::Failed => write!(f, "failed, {}", self.message),
+        }
+    }
+}
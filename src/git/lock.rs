@@ -0,0 +1,144 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+
+use super::{GifsyError, Repository};
+
+const RETRY: Duration = Duration::from_millis(200);
+
+/// Holds the lock of a repository, the lock file is removed when the
+/// last guard of the repository is dropped.
+pub struct LockGuard<'a> {
+    repo: &'a Repository,
+}
+
+impl<'a> Drop for LockGuard<'a> {
+    fn drop(&mut self) {
+        if self.repo.locks.fetch_sub(1, Ordering::SeqCst) == 1
+        {
+            // the file is closed and so unlocked after it's removed
+            if let Some((p, _file)) = self.repo.lock_file.lock().unwrap().take()
+            {
+                if let Err(e) = fs::remove_file(&p)
+                {
+                    warn!("can't remove lock {} ({})", p.display(), e);
+                }
+            }
+        }
+    }
+}
+
+/// takes the lock file returned by `path` unless `repo` already holds it
+pub fn acquire<'a, F>(repo: &'a Repository, path: F) -> Result<LockGuard<'a>, GifsyError>
+where
+    F: FnOnce() -> Result<PathBuf, GifsyError>,
+{
+    if repo.locks.fetch_add(1, Ordering::SeqCst) > 0
+    {
+        return Ok(LockGuard { repo });
+    }
+    // the guard gives the count back if we fail
+    let guard = LockGuard { repo };
+    let path = &path()?;
+    let deadline = Instant::now() + repo.lock_wait;
+    loop
+    {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(GifsyError::IoError)?;
+        // the kernel drops the lock of a process that died, a lock file
+        // left behind is never stale
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0
+        {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::EWOULDBLOCK)
+            {
+                return Err(GifsyError::IoError(e));
+            }
+            let pid = holder(&mut file);
+            if Instant::now() >= deadline
+            {
+                return Err(GifsyError::Locked(pid));
+            }
+            debug!("repository locked by {}, wait", pid);
+            thread::sleep(RETRY);
+            continue;
+        }
+        // the previous holder removes the file before it unlocks it, a
+        // lock on the removed file is no lock
+        if !same_file(&file, path)
+        {
+            continue;
+        }
+        file.set_len(0).map_err(GifsyError::IoError)?;
+        write!(file, "{}", process::id()).map_err(GifsyError::IoError)?;
+        *repo.lock_file.lock().unwrap() = Some((path.to_path_buf(), file));
+        debug!("locked {}", path.display());
+        return Ok(guard);
+    }
+}
+
+/// the pid the process holding the lock wrote, 0 if it didn't yet
+fn holder(file: &mut fs::File) -> u32 {
+    let mut pid = String::new();
+    match file.read_to_string(&mut pid)
+    {
+        Ok(_) => pid.trim().parse().unwrap_or(0),
+        Err(_) => 0,
+    }
+}
+
+/// true if `file` is still the one at `path`
+fn same_file(file: &fs::File, path: &Path) -> bool {
+    match (file.metadata(), fs::metadata(path))
+    {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process::Command;
+
+    use super::*;
+
+    #[test]
+    fn test_lock() {
+        let dir = env::temp_dir().join(format!("gifsy-lock-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        assert!(Command::new("git").current_dir(&dir).args(["init", "-q"]).status().unwrap().success());
+        let path = dir.to_str().unwrap();
+        let (a, b) = (Repository::from(path, "a").unwrap(), Repository::from(path, "b").unwrap());
+        let file = dir.join(".git").join("gifsy.lock");
+        // a lock file left behind by a process that died
+        fs::write(&file, "999999999").unwrap();
+        let guard = a.lock().unwrap();
+        let nested = a.lock().unwrap();
+        match b.lock()
+        {
+            Err(GifsyError::Locked(pid)) => assert_eq!(pid, process::id()),
+            _ => panic!("b took the lock held by a"),
+        }
+        drop(nested);
+        assert!(b.lock().is_err());
+        drop(guard);
+        assert!(!file.exists());
+        drop(b.lock().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
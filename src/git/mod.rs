@@ -1,25 +1,76 @@
 use std::error;
 use std::fmt;
+use std::fs;
 use std::io::{Error, Write};
 use std::path;
-use std::process::{Command, Stdio};
 use std::str;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Mutex;
+use std::time::Duration;
 use std::string::*;
 
 use chrono::prelude::*;
+use glob::Pattern;
 
-use self::parser::*;
 use super::notify;
 
+pub use self::backend::{Backend, Cli, GitBackend, Side};
+#[cfg(test)]
+pub use self::backend::Mock;
+pub use self::last_sync::{LastSync, Outcome};
+pub use self::lock::LockGuard;
+pub use self::process::Timeouts;
+pub use self::records::{Commit, FileStat, RemoteRef, SubmoduleCommit, SubmoduleState};
+pub use self::report::{Branch, Change, Entry, Report, Submodule};
+
+pub mod last_sync;
+pub mod lock;
+// `parsers!` pushes to the vector it builds
 #[macro_use]
+#[allow(clippy::vec_init_then_push)]
+#[cfg_attr(test, allow(clippy::assertions_on_constants))]
 pub mod parser;
+pub mod paths;
+#[allow(clippy::vec_init_then_push)]
+pub mod backend;
+pub mod process;
+pub mod records;
+pub mod report;
+
+/// the file in the git directory keeping the last synchronization
+const LAST_SYNC: &str = "gifsy-sync.toml";
 
 #[derive(Debug)]
 pub enum GifsyError {
     NoRepoitory,
     IoError(Error),
-    ParserError(String),
+    /// the output of the named git command didn't parse
+    Unparsable(&'static str, parser::ParseError),
     CmdFail(i32, String),
+    WatchError(String),
+    Interrupted(Interrupted),
+    Unresolved(Vec<path::PathBuf>),
+    /// the files `add` couldn't stage and why
+    AddFailed(Vec<(String, String)>),
+    /// git refused to commit, like a failing hook or nothing to commit
+    CommitFailed(String),
+    DetachedHead,
+    WrongBranch(String, String),
+    Diverged,
+    Locked(u32),
+    /// an error of libgit2
+    Libgit2(String),
+    /// a feature gifsy was built without
+    Unsupported(&'static str),
+    /// the named operation took too long and was stopped
+    Timeout(String),
+    /// gifsy was asked to terminate
+    Cancelled,
+    /// the remote branch moved since the last pull and the push was
+    /// rejected
+    Rejected(String),
+    /// the remote can't be reached, like without network
+    Offline(String),
 }
 impl fmt::Display for GifsyError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -31,16 +82,197 @@ impl fmt::Display for GifsyError {
             }
             GifsyError::NoRepoitory => write!(f, "the path is not a git repository"),
             GifsyError::IoError(ref e) => write!(f, "io error {}", e),
-            GifsyError::ParserError(..) => write!(f, "parser error"),
+            GifsyError::Unparsable(cmd, ref e) => write!(f, "can't parse git {}, {}", cmd, e),
+            GifsyError::WatchError(ref e) => write!(f, "watch error {}", e),
+            GifsyError::Interrupted(ref i) =>
+            {
+                write!(f, "{}, run gifsy recover --abort or --continue", i)
+            }
+            GifsyError::CommitFailed(ref e) => write!(f, "commit failed {}", e),
+            GifsyError::DetachedHead => write!(f, "HEAD is detached"),
+            GifsyError::Locked(pid) => write!(f, "repository locked by process {}", pid),
+            GifsyError::Libgit2(ref e) => write!(f, "libgit2 error {}", e),
+            GifsyError::Unsupported(what) => write!(f, "{} is not supported by this build", what),
+            GifsyError::Diverged => write!(f, "diverged from remote, can't fast-forward"),
+            GifsyError::Timeout(ref op) => write!(f, "git {} timed out", op),
+            GifsyError::Cancelled => write!(f, "cancelled"),
+            GifsyError::Offline(ref e) => write!(f, "remote unreachable {}", e),
+            GifsyError::Rejected(ref r) => write!(f, "push rejected, the remote changed meanwhile {}", r),
+            GifsyError::WrongBranch(ref expected, ref found) =>
+            {
+                write!(f, "on branch {} instead of {}", found, expected)
+            }
+            GifsyError::Unresolved(ref files) =>
+            {
+                let files: Vec<_> = files.iter().map(|f| paths::escape(f)).collect();
+                write!(f, "unmerged files left: {}", files.join(", "))
+            }
+            GifsyError::AddFailed(ref failures) =>
+            {
+                let files: Vec<String> = failures
+                    .iter()
+                    .map(|(file, e)| if file.is_empty() { e.clone() } else { format!("{} ({})", file, e) })
+                    .collect();
+                write!(f, "can't add {}", files.join(", "))
+            }
         }
     }
 }
 impl error::Error for GifsyError {
 }
 
+/// an unfinished operation a previous pull left behind
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Interrupted {
+    Rebase,
+    Merge,
+    /// the stash entry holding local changes whose autostash couldn't
+    /// be applied again
+    Autostash(String),
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self
+        {
+            Interrupted::Rebase => write!(f, "rebase in progress"),
+            Interrupted::Merge => write!(f, "merge in progress"),
+            Interrupted::Autostash(ref s) => write!(f, "autostash left in {}", s),
+        }
+    }
+}
+
+/// how `Repository::pull` integrates the remote changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PullStrategy {
+    Rebase,
+    Merge,
+    FfOnly,
+}
+
+impl fmt::Display for PullStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self
+        {
+            PullStrategy::Rebase => write!(f, "rebase"),
+            PullStrategy::Merge => write!(f, "merge"),
+            PullStrategy::FfOnly => write!(f, "ff-only"),
+        }
+    }
+}
+
+impl str::FromStr for PullStrategy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<PullStrategy, String> {
+        match s
+        {
+            "rebase" => Ok(PullStrategy::Rebase),
+            "merge" => Ok(PullStrategy::Merge),
+            "ff-only" => Ok(PullStrategy::FfOnly),
+            s => Err(format!("unknown pull strategy {}", s)),
+        }
+    }
+}
+
+/// how `Repository::add` resolves an unmerged file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictStrategy {
+    /// leave the file for a human
+    Manual,
+    /// the side of `git checkout --ours`, the remote version while a
+    /// rebase or the autostash is interrupted
+    Ours,
+    /// the side of `git checkout --theirs`
+    Theirs,
+    /// the version of this repository
+    Local,
+    /// the version pulled from the remote
+    Remote,
+    /// take the remote version and save the local one as
+    /// `<file>.conflict-<host>`
+    KeepBoth,
+}
+
+impl fmt::Display for ConflictStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self
+        {
+            ConflictStrategy::Manual => write!(f, "manual"),
+            ConflictStrategy::Ours => write!(f, "ours"),
+            ConflictStrategy::Theirs => write!(f, "theirs"),
+            ConflictStrategy::Local => write!(f, "local"),
+            ConflictStrategy::Remote => write!(f, "remote"),
+            ConflictStrategy::KeepBoth => write!(f, "keep-both"),
+        }
+    }
+}
+
+impl str::FromStr for ConflictStrategy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<ConflictStrategy, String> {
+        match s
+        {
+            "manual" => Ok(ConflictStrategy::Manual),
+            "ours" => Ok(ConflictStrategy::Ours),
+            "theirs" => Ok(ConflictStrategy::Theirs),
+            "local" => Ok(ConflictStrategy::Local),
+            "remote" => Ok(ConflictStrategy::Remote),
+            "keep-both" => Ok(ConflictStrategy::KeepBoth),
+            s => Err(format!("unknown conflict strategy {}", s)),
+        }
+    }
+}
+
+/// the side of an unmerged file holding the local version. A rebase
+/// replays the local commits onto the remote branch and the autostash is
+/// applied onto the pulled tree, both make the remote version git's ours.
+fn local_side(op: Option<&Interrupted>) -> Side {
+    match op
+    {
+        Some(&Interrupted::Rebase) | Some(&Interrupted::Autostash(_)) => Side::Theirs,
+        Some(&Interrupted::Merge) | None => Side::Ours,
+    }
+}
+
+/// a strategy for the files matching a glob
+#[derive(Debug, Clone)]
+pub struct ConflictRule {
+    pattern: Pattern,
+    strategy: ConflictStrategy,
+}
+
+impl ConflictRule {
+    pub fn new(glob: &str, strategy: ConflictStrategy) -> Result<ConflictRule, glob::PatternError> {
+        Ok(ConflictRule {
+            pattern: Pattern::new(glob)?,
+            strategy,
+        })
+    }
+}
+
+impl fmt::Display for ConflictRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {}", self.pattern, self.strategy)
+    }
+}
+
 pub struct Repository {
     path: String,
     name: String,
+    remote: String,
+    branch: Option<String>,
+    pull_strategy: PullStrategy,
+    conflict: ConflictStrategy,
+    conflict_rules: Vec<ConflictRule>,
+    lock_wait: Duration,
+    push_retries: u32,
+    push_backoff: Duration,
+    locks: AtomicUsize,
+    /// the lock file and its open file holding the `flock`
+    lock_file: Mutex<Option<(path::PathBuf, fs::File)>>,
+    backend: Box<dyn GitBackend>,
 }
 
 impl Repository {
@@ -51,6 +283,17 @@ impl Repository {
             Ok(Repository {
                 path: path.to_owned(),
                 name: name.to_owned(),
+                remote: String::from("origin"),
+                branch: None,
+                pull_strategy: PullStrategy::Rebase,
+                conflict: ConflictStrategy::Manual,
+                conflict_rules: Vec::new(),
+                lock_wait: Duration::from_secs(0),
+                push_retries: 3,
+                push_backoff: Duration::from_secs(1),
+                locks: AtomicUsize::new(0),
+                lock_file: Mutex::new(None),
+                backend: Box::new(Cli::new(path)),
             })
         }
         else
@@ -58,354 +301,851 @@ impl Repository {
             Err(GifsyError::NoRepoitory)
         }
     }
+    /// replaces the git executable with another backend
+    pub fn with_backend(mut self, backend: Box<dyn GitBackend>) -> Repository {
+        self.backend = backend;
+        self
+    }
+    /// sets the remote and the branch to pull from and push to, without
+    /// a branch the upstream of the current branch is used
+    pub fn with_remote(mut self, remote: &str, branch: Option<&str>) -> Repository {
+        self.remote = remote.to_owned();
+        self.branch = branch.map(|b| b.to_owned());
+        self
+    }
+    /// sets how long `lock` waits for another process to release the
+    /// repository
+    pub fn with_lock_wait(mut self, wait: Duration) -> Repository {
+        self.lock_wait = wait;
+        self
+    }
+    /// sets how often `sync` pulls again and retries a rejected push, the
+    /// wait before a retry starts at `backoff` and doubles every time
+    pub fn with_push_retries(mut self, retries: u32, backoff: Duration) -> Repository {
+        self.push_retries = retries;
+        self.push_backoff = backoff;
+        self
+    }
+    pub fn with_pull_strategy(mut self, strategy: PullStrategy) -> Repository {
+        self.pull_strategy = strategy;
+        self
+    }
+    /// sets how unmerged files get resolved, the first rule matching a
+    /// file wins over the `default` strategy
+    pub fn with_conflicts(mut self, default: ConflictStrategy, rules: Vec<ConflictRule>) -> Repository {
+        self.conflict = default;
+        self.conflict_rules = rules;
+        self
+    }
+    /// Takes the lock of the repository, every mutating operation does so
+    /// but a caller can hold it across several operations. Fails with
+    /// `GifsyError::Locked` if another process holds it longer than the
+    /// lock wait time.
+    pub fn lock(&self) -> Result<LockGuard<'_>, GifsyError> {
+        lock::acquire(self, || self.backend.git_path("gifsy.lock"))
+    }
     pub fn name(&self) -> String {
         self.name.clone()
     }
+    pub fn path(&self) -> &path::Path {
+        path::Path::new(&self.path)
+    }
     pub fn status(&self) -> Result<Vec<Box<Status>>, GifsyError> {
-        match Command::new("git")
-            .current_dir(&self.path)
-            .arg("status")
-            .arg("--porcelain")
-            .arg("-z")
-            .output()
-        {
-            Err(e) => Err(GifsyError::IoError(e)),
-            Ok(output) =>
+        self.backend.status()
+    }
+    /// the status with the branch, the ahead and behind counts and the
+    /// states of the submodules
+    pub fn report(&self) -> Result<Report, GifsyError> {
+        self.backend.report()
+    }
+    /// fetches the remote branch without touching the working tree
+    pub fn fetch(&self) -> Result<(), GifsyError> {
+        let _lock = self.lock()?;
+        self.backend.fetch(&self.remote, self.branch.as_deref())
+    }
+    /// commits of the remote branch missing locally, newest first
+    pub fn incoming(&self) -> Result<Vec<Commit>, GifsyError> {
+        self.backend.log(&format!("HEAD..{}", self.tracking_ref()))
+    }
+    /// local commits missing on the remote branch, newest first
+    pub fn outgoing(&self) -> Result<Vec<Commit>, GifsyError> {
+        self.backend.log(&format!("{}..HEAD", self.tracking_ref()))
+    }
+    /// the files the remote branch changed since it forked from HEAD
+    pub fn incoming_changes(&self) -> Result<Vec<FileStat>, GifsyError> {
+        self.backend.numstat("HEAD", &self.tracking_ref())
+    }
+    /// the submodules and their checked out commits
+    pub fn submodules(&self) -> Result<Vec<SubmoduleCommit>, GifsyError> {
+        self.backend.submodules()
+    }
+    /// submodules `submodules_init` and `submodules_update` would touch
+    pub fn submodules_pending(&self) -> Result<Vec<SubmoduleCommit>, GifsyError> {
+        Ok(self
+            .submodules()?
+            .into_iter()
+            .filter(|s| s.state != SubmoduleState::Current)
+            .collect())
+    }
+    /// object id of the upstream branch as known locally
+    pub fn upstream_head(&self) -> Result<String, GifsyError> {
+        self.backend.rev_parse(&self.tracking_ref())
+    }
+    /// object id of the upstream branch on the remote, `None` if the
+    /// branch doesn't exist there
+    pub fn remote_head(&self) -> Result<Option<String>, GifsyError> {
+        let remote_ref = self.remote_ref()?;
+        Ok(self
+            .backend
+            .ls_remote(&self.remote, &[&remote_ref])?
+            .into_iter()
+            .find(|r| r.name == remote_ref)
+            .map(|r| r.oid))
+    }
+    /// the branches and tags of the remote
+    pub fn remote_refs(&self) -> Result<Vec<RemoteRef>, GifsyError> {
+        self.backend.ls_remote(&self.remote, &[])
+    }
+    /// the checked out branch
+    pub fn current_branch(&self) -> Result<String, GifsyError> {
+        self.backend.current_branch()
+    }
+    /// true if the current branch has an upstream branch configured
+    pub fn has_upstream(&self) -> Result<bool, GifsyError> {
+        Ok(self.backend.upstream()?.is_some())
+    }
+    /// number of local commits not yet pushed to the upstream branch
+    pub fn unpushed(&self) -> Result<usize, GifsyError> {
+        Ok(self.outgoing()?.len())
+    }
+    /// the number of commits the next push sends, all commits of HEAD
+    /// without a remote branch, 0 if they can't be counted
+    pub fn to_push(&self) -> usize {
+        match self.unpushed()
+        {
+            Ok(n) => n,
+            Err(e) =>
             {
-                if output.status.success()
-                {
-                    let rest = String::from_utf8_lossy(&output.stdout);
-                    let p = parsers![parse_index, parse_tree, parse_from, parse_to];
-                    match parse::<Vec<&Status>>(&rest, p)
-                    {
-                        Err(e) => Err(GifsyError::ParserError(e.to_string())),
-                        Ok(status) => Ok(status),
-                    }
-                }
-                else
-                {
-                    Err(GifsyError::CmdFail(
-                        output.status.code().unwrap(),
-                        String::from_utf8_lossy(&output.stderr).to_string(),
-                    ))
-                }
+                debug!("no remote branch yet ({})", e);
+                self.backend.count("HEAD").unwrap_or_else(|e| {
+                    debug!("can't count the commits ({})", e);
+                    0
+                })
             }
         }
     }
+    /// returns the subset of `files` that git ignores
+    pub fn check_ignore(&self, files: &[path::PathBuf]) -> Result<Vec<path::PathBuf>, GifsyError> {
+        if files.is_empty()
+        {
+            return Ok(Vec::new());
+        }
+        self.backend.check_ignore(files)
+    }
     pub fn add(&self, status: Vec<Box<Status>>) -> Result<Vec<Box<Status>>, GifsyError> {
+        let _lock = self.lock()?;
+        self.check_state()?;
+        self.stage(status)
+    }
+    #[allow(clippy::vec_box)]
+    fn stage(&self, status: Vec<Box<Status>>) -> Result<Vec<Box<Status>>, GifsyError> {
         let mut rc = Vec::new();
+        let mut files = Vec::new();
+        let mut removed = Vec::new();
+        let local = if status.iter().any(|s| s.is_unmerged())
+        {
+            local_side(self.interrupted()?.as_ref())
+        }
+        else
+        {
+            Side::Ours
+        };
         for s in &status
         {
-            if s.is_unmerged()
+            match s.skip()
             {
-                warn!("unmerged file {}", s);
-                let msg = format!("File {} need to be manually merged", s.file());
-                notify::send("GIt FileSYncronization needs attension", &msg);
-                continue;
+                Some(Skip::Unmerged) =>
+                {
+                    if self.resolve(s, local)?
+                    {
+                        rc.push(s.clone());
+                    }
+                    else
+                    {
+                        warn!("unmerged file {}", s);
+                        let msg = format!("File {} need to be manually merged", paths::escape(s.file()));
+                        notify::send("GIt FileSYncronization needs attension", &msg);
+                    }
+                    continue;
+                }
+                None => (),
             }
-            let to_file = s.file();
             debug!("Status: {:?}", s);
-            if s.index == 'D'
+            match (s.index(), s.tree())
             {
-                continue;
-            };
-            //let msg = format!("{} modified", to_file);
-            //notify::send("gifsy sync", &msg);
-            let output = Command::new("git")
-                .current_dir(&self.path)
-                .arg("add")
-                .arg(&to_file)
-                .output()
-                .expect("can't execute git add");
-
-            if !output.status.success()
-            {
-                return Err(GifsyError::CmdFail(
-                    output.status.code().unwrap_or(-5),
-                    format!(
-                        "can't add {} ({})",
-                        &to_file,
-                        String::from_utf8_lossy(&output.stderr)
-                    ),
-                ));
+                // the deletion is staged already
+                (ChangeKind::Deleted, ChangeKind::Unmodified) => (),
+                (_, ChangeKind::Deleted) => removed.push(s.file().to_path_buf()),
+                // git only sees renames in the working tree of files added
+                // with intent to add, the source is gone already
+                (_, ChangeKind::Renamed) =>
+                {
+                    files.push(s.file().to_path_buf());
+                    removed.extend(s.source().map(|f| f.to_path_buf()));
+                }
+                _ => files.push(s.file().to_path_buf()),
             }
             rc.push(s.clone());
         }
+        if !files.is_empty()
+        {
+            let files: Vec<&path::Path> = files.iter().map(|f| f.as_path()).collect();
+            self.backend.add(&files)?;
+        }
+        if !removed.is_empty()
+        {
+            let removed: Vec<&path::Path> = removed.iter().map(|f| f.as_path()).collect();
+            self.backend.remove(&removed)?;
+        }
         Ok(rc)
     }
-    pub fn commit(&self, status: Vec<Box<Status>>) -> Result<(), GifsyError> {
-        let process = match Command::new("git")
-            .current_dir(&self.path)
-            .arg("commit")
-            .arg("--file")
-            .arg("-")
-            .stdin(Stdio::piped())
-            .spawn()
-        {
-            Err(e) => return Err(GifsyError::IoError(e)),
-            Ok(process) => process,
+    /// finds a rebase or autostash a previous pull couldn't finish
+    pub fn interrupted(&self) -> Result<Option<Interrupted>, GifsyError> {
+        for dir in &["rebase-merge", "rebase-apply"]
+        {
+            if self.backend.git_path(dir)?.is_dir()
+            {
+                return Ok(Some(Interrupted::Rebase));
+            }
+        }
+        if self.backend.git_path("MERGE_HEAD")?.is_file()
+        {
+            return Ok(Some(Interrupted::Merge));
+        }
+        Ok(self
+            .backend
+            .stashes()?
+            .into_iter()
+            .find(|(_, subject)| subject == "autostash")
+            .map(|(stash, _)| Interrupted::Autostash(stash)))
+    }
+    /// fails if a previous pull was interrupted, HEAD is detached or
+    /// another than the configured branch is checked out
+    pub fn check_state(&self) -> Result<(), GifsyError> {
+        if let Some(i) = self.interrupted()?
+        {
+            return Err(GifsyError::Interrupted(i));
+        }
+        let current = self.current_branch()?;
+        match self.branch
+        {
+            Some(ref b) if *b != current => Err(GifsyError::WrongBranch(b.clone(), current)),
+            _ => Ok(()),
+        }
+    }
+    /// throws away the interrupted rebase, a failed autostash is undone
+    /// and its changes are kept as a regular stash entry
+    pub fn recover_abort(&self) -> Result<Option<Interrupted>, GifsyError> {
+        let _lock = self.lock()?;
+        let interrupted = self.interrupted()?;
+        if let Some(ref i) = interrupted
+        {
+            self.backend.abort(i)?;
+        }
+        Ok(interrupted)
+    }
+    /// stages the files resolved by hand or by the conflict strategies
+    /// and finishes the interrupted rebase or autostash
+    pub fn recover_continue(&self) -> Result<Option<Interrupted>, GifsyError> {
+        let _lock = self.lock()?;
+        let interrupted = self.interrupted()?;
+        let i = match interrupted
+        {
+            Some(ref i) => i,
+            None => return Ok(None),
         };
-        let msg = create_commit_message(&status, &self.name).unwrap();
-        match process.stdin.unwrap().write_all(msg.as_bytes())
+        self.stage(self.status()?)?;
+        let unmerged: Vec<path::PathBuf> = self
+            .status()?
+            .iter()
+            .filter(|s| s.is_unmerged())
+            .map(|s| s.file().to_path_buf())
+            .collect();
+        if !unmerged.is_empty()
+        {
+            return Err(GifsyError::Unresolved(unmerged));
+        }
+        self.backend.resume(i)?;
+        Ok(interrupted)
+    }
+    /// the strategy configured for `file`, the patterns don't match
+    /// names that aren't UTF-8
+    pub fn conflict_strategy(&self, file: &path::Path) -> ConflictStrategy {
+        self.conflict_rules
+            .iter()
+            .find(|r| r.pattern.matches_path(file))
+            .map_or(self.conflict, |r| r.strategy)
+    }
+    /// resolves and stages an unmerged file, returns false if it is left
+    /// for manual merging
+    fn resolve(&self, s: &Status, local: Side) -> Result<bool, GifsyError> {
+        let file = s.file();
+        // a deleted side has no stage to check out
+        let deleted = |side: Side| match s.kind
         {
-            Err(e) => Err(GifsyError::IoError(e)),
-            Ok(_) =>
+            StatusKind::Unmerged { conflict } => match side
             {
-                let mut msg = String::from("the following files have been changed:\n\n");
-                if !status.is_empty()
+                Side::Ours => !conflict.has_ours(),
+                Side::Theirs => !conflict.has_theirs(),
+            },
+            _ => false,
+        };
+        let remote = local.other();
+        let strategy = self.conflict_strategy(file);
+        let side = match strategy
+        {
+            ConflictStrategy::Manual => return Ok(false),
+            ConflictStrategy::Ours => Side::Ours,
+            ConflictStrategy::Theirs => Side::Theirs,
+            ConflictStrategy::Local => local,
+            ConflictStrategy::Remote => remote,
+            ConflictStrategy::KeepBoth if deleted(remote) => local,
+            ConflictStrategy::KeepBoth =>
+            {
+                if !deleted(local)
                 {
-                    for s in &status
-                    {
-                        let f = format!("  {}\n", &s.file());
-                        msg += &f;
-                    }
-                    notify::send("GIt FileSYncronization Files Modified", &msg);
+                    let host: String = self
+                        .name
+                        .chars()
+                        .map(|c| if c.is_whitespace() || c == '/' { '-' } else { c })
+                        .collect();
+                    let mut copy = file.as_os_str().to_owned();
+                    copy.push(format!(".conflict-{}", host));
+                    let copy = path::PathBuf::from(copy);
+                    let content = self.backend.show(file, local)?;
+                    fs::write(self.path().join(&copy), content).map_err(GifsyError::IoError)?;
+                    self.backend.add(&[&copy])?;
                 }
-                Ok(())
+                remote
+            }
+        };
+        if deleted(side)
+        {
+            self.backend.remove(&[file])?;
+        }
+        else
+        {
+            self.backend.checkout(file, side)?;
+            self.backend.add(&[file])?;
+        }
+        let file = paths::escape(file);
+        info!("resolved conflict in {} with {}", file, strategy);
+        let msg = format!("Conflict in {} resolved with {}", file, strategy);
+        notify::send("GIt FileSYncronization resolved a conflict", &msg);
+        Ok(true)
+    }
+    /// commits the index and returns the id of the new commit
+    pub fn commit(&self, status: Vec<Box<Status>>) -> Result<String, GifsyError> {
+        let _lock = self.lock()?;
+        self.check_state()?;
+        let msg = create_commit_message(&status, &self.name).unwrap();
+        let id = self.backend.commit(&msg)?;
+        let mut msg = format!("commit {} changed the following files:\n\n", id);
+        if !status.is_empty()
+        {
+            for s in &status
+            {
+                let f = format!("  {}\n", paths::escape(s.file()));
+                msg += &f;
             }
+            notify::send("GIt FileSYncronization Files Modified", &msg);
         }
+        Ok(id)
     }
     pub fn pull(&self) -> Result<(), GifsyError> {
-        let output = Command::new("git")
-            .current_dir(&self.path)
-            .arg("pull")
-            .arg("origin")
-            .arg("--rebase")
-            .arg("--autostash")
-            .output()
-            .expect("can't execute git pull origin");
-
-        debug!(
-            "pull output stdout: {}",
-            String::from_utf8_lossy(&output.stdout)
-        );
-        debug!(
-            "pull output stderr: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        debug!("pull status: {}", output.status);
-        if output.status.success()
+        let _lock = self.lock()?;
+        self.check_state()?;
+        let mut refspec = self.branch.clone();
+        if !self.has_upstream()?
         {
-            match output.status.code()
+            if self.remote_head()?.is_none()
             {
-                Some(rc) =>
+                info!("nothing to pull, the remote branch doesn't exist yet");
+                return Ok(());
+            }
+            refspec = Some(self.current_branch()?);
+        }
+        match self.backend.pull(&self.remote, refspec.as_deref(), self.pull_strategy)
+        {
+            Ok(()) => Ok(()),
+            Err(e @ GifsyError::Offline(_)) => Err(e),
+            Err(e) =>
+            {
+                if let Some(i) = self.interrupted()?
                 {
-                    if rc != 0
-                    {
-                      dbg!(&output);
-                        Err(GifsyError::CmdFail(
-                            rc,
-                            format!(
-                                "pull failed: {} err: {}",
-                                String::from_utf8_lossy(&output.stdout),
-                                String::from_utf8_lossy(&output.stderr)
-                            ),
-                        ))
-                    }
-                    else
-                    {
-                        Ok(())
-                    }
+                    return Err(GifsyError::Interrupted(i));
+                }
+                if self.pull_strategy == PullStrategy::FfOnly && self.diverged(refspec.as_deref())
+                {
+                    return Err(GifsyError::Diverged);
                 }
-                None => Ok(()),
+                Err(e)
             }
         }
-        else
+    }
+    /// true if HEAD isn't part of the freshly fetched remote branch,
+    /// without a fetch or if it can't be told the error of the pull counts
+    fn diverged(&self, refspec: Option<&str>) -> bool {
+        if let Err(e) = self.backend.fetch(&self.remote, refspec)
+        {
+            debug!("can't fetch to check for divergence ({})", e);
+            return false;
+        }
+        match self.backend.is_ancestor("HEAD", &self.tracking_ref())
         {
-            Err(GifsyError::CmdFail(
-                output.status.code().unwrap_or(-1),
-                format!(
-                    "couldn't call git pull: {} err: {}",
-                    String::from_utf8_lossy(&output.stdout),
-                    String::from_utf8_lossy(&output.stderr)
-                ),
-            ))
+            Ok(ancestor) => !ancestor,
+            Err(e) =>
+            {
+                debug!("can't check for divergence ({})", e);
+                false
+            }
         }
     }
     pub fn push(&self) -> Result<(), GifsyError> {
-        let output = Command::new("git")
-            .current_dir(&self.path)
-            .arg("push")
-            .arg("origin")
-            .output()
-            .expect("can't execute git push");
-
-        debug!(
-            "push output stdout: {}",
-            String::from_utf8_lossy(&output.stdout)
-        );
-        debug!(
-            "push output stderr: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        debug!("push status: {}", output.status);
-        if output.status.success()
+        let _lock = self.lock()?;
+        self.check_state()?;
+        let mut refspec = self.branch.clone();
+        let mut set_upstream = false;
+        if !self.has_upstream()?
+        {
+            info!("set upstream branch on first push");
+            set_upstream = true;
+            refspec = Some(self.current_branch()?);
+        }
+        self.backend.push(&self.remote, refspec.as_deref(), set_upstream)
+    }
+    /// pushes, if another host pushed meanwhile its changes get pulled
+    /// and the push is retried
+    fn push_retrying(&self) -> Result<(), GifsyError> {
+        let mut backoff = self.push_backoff;
+        let mut retries = 0;
+        loop
         {
-            match output.status.code()
+            match self.push()
             {
-                Some(rc) =>
+                Err(GifsyError::Rejected(ref r)) if retries < self.push_retries =>
                 {
-                    if rc != 0
-                    {
-                        Err(GifsyError::CmdFail(
-                            rc,
-                            format!(
-                                "can't push: {} err: {}",
-                                String::from_utf8_lossy(&output.stdout),
-                                String::from_utf8_lossy(&output.stderr)
-                            ),
-                        ))
-                    }
-                    else
-                    {
-                        Ok(())
-                    }
+                    retries += 1;
+                    info!("push rejected {}, pull again in {:?}", r, backoff);
+                    process::sleep(backoff)?;
+                    backoff *= 2;
+                    self.pull()?;
                 }
-                None => Ok(()),
+                r => return r,
             }
         }
-        else
+    }
+    pub fn submodules_init(&self) -> Result<(), GifsyError> {
+        let _lock = self.lock()?;
+        self.backend.submodules_init()
+    }
+    pub fn submodules_update(&self) -> Result<(), GifsyError> {
+        let _lock = self.lock()?;
+        self.backend.submodules_update()
+    }
+    /// commits the local changes, pulls the remote changes, updates the
+    /// submodules and pushes, all while holding the lock. If the remote
+    /// can't be reached the local changes are committed only.
+    /// the time and outcome are recorded for `last_sync`, unless another
+    /// gifsy holds the lock
+    pub fn sync(&self) -> Result<Synced, GifsyError> {
+        let _lock = self.lock()?;
+        let result = self.synchronize();
+        let recorded = self
+            .backend
+            .git_path(LAST_SYNC)
+            .and_then(|f| LastSync::new(&result).write(&f));
+        if let Err(e) = recorded
         {
-            Err(GifsyError::CmdFail(
-                output.status.code().unwrap_or(-2),
-                format!(
-                    "couldn't call git push: {} err: {}",
-                    String::from_utf8_lossy(&output.stdout),
-                    String::from_utf8_lossy(&output.stderr)
-                ),
-            ))
+            warn!("can't record the synchronization {}", e);
         }
+        result
     }
-    pub fn submodules_init(&self) -> Result<(), GifsyError> {
-        let output = Command::new("git")
-            .current_dir(&self.path)
-            .arg("submodule")
-            .arg("init")
-            .output()
-            .expect("can't execute git submodule init");
-
-        if output.status.success()
+    /// Synchronizes if there are local changes or the branch has no
+    /// upstream branch yet, otherwise only pulls if the remote branch
+    /// moved.
+    pub fn poll(&self) -> Result<Synced, GifsyError> {
+        let _lock = self.lock()?;
+        // the first push sets the upstream branch
+        if !self.has_upstream()?
+        {
+            info!("no upstream branch yet, synchronize");
+            return self.sync();
+        }
+        if !self.report()?.is_clean() || self.unpushed()? > 0
         {
-            match output.status.code()
+            info!("local changes found, synchronize");
+            return self.sync();
+        }
+        let upstream = self.upstream_head()?;
+        match self.remote_head()?
+        {
+            Some(ref remote) if *remote != upstream =>
             {
-                Some(rc) =>
+                info!("remote changed, pull changes");
+                match self.pull()
                 {
-                    if rc != 0
-                    {
-                        Err(GifsyError::CmdFail(
-                            rc,
-                            format!(
-                                "can't init submodules: {} err: {}",
-                                String::from_utf8_lossy(&output.stdout),
-                                String::from_utf8_lossy(&output.stderr)
-                            ),
-                        ))
-                    }
-                    else
-                    {
-                        Ok(())
-                    }
+                    Err(GifsyError::Offline(ref e)) => return self.offline(e),
+                    r => r?,
                 }
-                None => Ok(()),
+                debug!("handle submodules");
+                self.submodules_init()?;
+                self.submodules_update()?;
+            }
+            _ => debug!("remote unchanged"),
+        }
+        Ok(Synced::Done)
+    }
+    /// the last synchronization, `None` if there was none yet
+    pub fn last_sync(&self) -> Result<Option<LastSync>, GifsyError> {
+        LastSync::read(&self.backend.git_path(LAST_SYNC)?)
+    }
+    fn synchronize(&self) -> Result<Synced, GifsyError> {
+        debug!("synchronize repository");
+        let mut status = self.status()?;
+        if !status.is_empty()
+        {
+            debug!("add local changes");
+            self.add(status)?;
+            debug!("update local status");
+            status = self.status()?;
+            // a submodule with only untracked content is listed but
+            // nothing of it is staged
+            status.retain(|s| s.index() != ChangeKind::Unmodified);
+            if status.is_empty()
+            {
+                debug!("nothing staged to commit");
+            }
+            else
+            {
+                info!("commit local changes");
+                let id = self.commit(status)?;
+                info!("committed local changes as {}", id);
             }
         }
         else
         {
-            Err(GifsyError::CmdFail(
-                output.status.code().unwrap_or(-3),
-                format!(
-                    "couldn't call init submodules: {} err: {}",
-                    String::from_utf8_lossy(&output.stdout),
-                    String::from_utf8_lossy(&output.stderr)
-                ),
-            ))
+            debug!("no local changes");
         }
+        info!("pull changes");
+        match self.pull()
+        {
+            Err(GifsyError::Offline(ref e)) => return self.offline(e),
+            r => r?,
+        }
+        debug!("handle submodules");
+        self.submodules_init()?;
+        self.submodules_update()?;
+        info!("push changes");
+        match self.push_retrying()
+        {
+            Err(GifsyError::Offline(ref e)) => return self.offline(e),
+            r => r?,
+        }
+        Ok(Synced::Done)
     }
-    pub fn submodules_update(&self) -> Result<(), GifsyError> {
-        let output = Command::new("git")
-            .current_dir(&self.path)
-            .arg("submodule")
-            .arg("update")
-            .output()
-            .expect("can't execute git submodule update");
+    fn offline(&self, reason: &str) -> Result<Synced, GifsyError> {
+        // an error mustn't hide that the remote is unreachable
+        let unpushed = self.to_push();
+        info!("offline, {} commits not pushed yet ({})", unpushed, reason);
+        Ok(Synced::Offline(unpushed))
+    }
+}
 
-        if output.status.success()
+impl Repository {
+    /// the local ref tracking the remote branch
+    fn tracking_ref(&self) -> String {
+        match self.branch
         {
-            match output.status.code()
-            {
-                Some(rc) =>
-                {
-                    if rc != 0
-                    {
-                        Err(GifsyError::CmdFail(
-                            rc,
-                            format!(
-                                "can't update submodules: {} err: {}",
-                                String::from_utf8_lossy(&output.stdout),
-                                String::from_utf8_lossy(&output.stderr)
-                            ),
-                        ))
-                    }
-                    else
-                    {
-                        Ok(())
-                    }
-                }
-                None => Ok(()),
-            }
+            Some(ref b) => format!("refs/remotes/{}/{}", self.remote, b),
+            None => String::from("@{upstream}"),
         }
-        else
+    }
+    /// the name of the remote branch on the remote
+    fn remote_ref(&self) -> Result<String, GifsyError> {
+        if let Some(ref b) = self.branch
+        {
+            return Ok(format!("refs/heads/{}", b));
+        }
+        match self.backend.upstream()?
+        {
+            Some(r) => Ok(r),
+            None => Ok(format!("refs/heads/{}", self.current_branch()?)),
+        }
+    }
+}
+
+/// how far `sync` got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Synced {
+    /// the remote has the local changes and the other way round
+    Done,
+    /// the remote was unreachable, the local changes are committed and
+    /// the number of commits waiting for a push is given
+    Offline(usize),
+}
+
+/// what happened to a file in the index or the working tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeKind {
+    Unmodified,
+    Added,
+    Modified,
+    Deleted,
+    Renamed,
+    Copied,
+    /// a file became a symlink or the other way round
+    TypeChanged,
+}
+
+impl ChangeKind {
+    /// the flag of `git status --porcelain`
+    pub fn flag(self) -> char {
+        match self
+        {
+            ChangeKind::Unmodified => ' ',
+            ChangeKind::Added => 'A',
+            ChangeKind::Modified => 'M',
+            ChangeKind::Deleted => 'D',
+            ChangeKind::Renamed => 'R',
+            ChangeKind::Copied => 'C',
+            ChangeKind::TypeChanged => 'T',
+        }
+    }
+    pub fn from_flag(flag: char) -> Option<ChangeKind> {
+        match flag
+        {
+            ' ' => Some(ChangeKind::Unmodified),
+            'A' => Some(ChangeKind::Added),
+            'M' => Some(ChangeKind::Modified),
+            'D' => Some(ChangeKind::Deleted),
+            'R' => Some(ChangeKind::Renamed),
+            'C' => Some(ChangeKind::Copied),
+            'T' => Some(ChangeKind::TypeChanged),
+            _ => None,
+        }
+    }
+}
+
+/// the state of a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "kebab-case")]
+pub enum StatusKind {
+    /// the change staged in the index and the one left in the working
+    /// tree
+    Tracked { index: ChangeKind, tree: ChangeKind },
+    Untracked,
+    Ignored,
+    Unmerged { conflict: Conflict },
+}
+
+/// what both sides of a conflict did to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Conflict {
+    BothDeleted,
+    AddedByUs,
+    DeletedByThem,
+    AddedByThem,
+    DeletedByUs,
+    BothAdded,
+    BothModified,
+}
+
+impl Conflict {
+    /// the two letters of `git status --porcelain`
+    pub fn flags(self) -> (char, char) {
+        match self
+        {
+            Conflict::BothDeleted => ('D', 'D'),
+            Conflict::AddedByUs => ('A', 'U'),
+            Conflict::DeletedByThem => ('U', 'D'),
+            Conflict::AddedByThem => ('U', 'A'),
+            Conflict::DeletedByUs => ('D', 'U'),
+            Conflict::BothAdded => ('A', 'A'),
+            Conflict::BothModified => ('U', 'U'),
+        }
+    }
+    pub fn from_flags(index: char, tree: char) -> Option<Conflict> {
+        match (index, tree)
+        {
+            ('D', 'D') => Some(Conflict::BothDeleted),
+            ('A', 'U') => Some(Conflict::AddedByUs),
+            ('U', 'D') => Some(Conflict::DeletedByThem),
+            ('U', 'A') => Some(Conflict::AddedByThem),
+            ('D', 'U') => Some(Conflict::DeletedByUs),
+            ('A', 'A') => Some(Conflict::BothAdded),
+            ('U', 'U') => Some(Conflict::BothModified),
+            _ => None,
+        }
+    }
+    /// our side has a stage to check out
+    pub fn has_ours(self) -> bool {
+        !matches!(self, Conflict::BothDeleted | Conflict::DeletedByUs | Conflict::AddedByThem)
+    }
+    /// their side has a stage to check out
+    pub fn has_theirs(self) -> bool {
+        !matches!(self, Conflict::BothDeleted | Conflict::DeletedByThem | Conflict::AddedByUs)
+    }
+}
+
+impl StatusKind {
+    /// the two letters of `git status --porcelain`
+    pub fn from_flags(index: char, tree: char) -> Option<StatusKind> {
+        if let Some(conflict) = Conflict::from_flags(index, tree)
+        {
+            return Some(StatusKind::Unmerged { conflict });
+        }
+        match (index, tree)
+        {
+            ('?', '?') => Some(StatusKind::Untracked),
+            ('!', '!') => Some(StatusKind::Ignored),
+            _ => Some(StatusKind::Tracked {
+                index: ChangeKind::from_flag(index)?,
+                tree: ChangeKind::from_flag(tree)?,
+            }),
+        }
+    }
+    pub fn flags(self) -> (char, char) {
+        match self
         {
-            Err(GifsyError::CmdFail(
-                output.status.code().unwrap_or(-4),
-                format!(
-                    "couldn't call update submodules: {} err: {}",
-                    String::from_utf8_lossy(&output.stdout),
-                    String::from_utf8_lossy(&output.stderr)
-                ),
-            ))
+            StatusKind::Tracked { index, tree } => (index.flag(), tree.flag()),
+            StatusKind::Untracked => ('?', '?'),
+            StatusKind::Ignored => ('!', '!'),
+            StatusKind::Unmerged { conflict } => conflict.flags(),
         }
     }
 }
 
-#[derive(Clone)]
+/// The change of a file, its paths are kept as git printed them, so they
+/// need not be UTF-8.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Status {
-    index: char,
-    tree: char,
-    from_file: String,
-    to_file: String,
+    #[serde(flatten)]
+    kind: StatusKind,
+    /// the file, the source of a rename or copy
+    from_file: path::PathBuf,
+    /// the destination of a rename or copy
+    #[serde(default, skip_serializing_if = "is_empty")]
+    to_file: path::PathBuf,
+}
+
+fn is_empty(p: &path::Path) -> bool {
+    p.as_os_str().is_empty()
+}
+
+/// why `Repository::add` leaves a file alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Skip {
+    Unmerged,
+}
+
+impl fmt::Display for Skip {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self
+        {
+            Skip::Unmerged => write!(f, "unmerged"),
+        }
+    }
 }
 
 impl Status {
+    pub fn new(kind: StatusKind, file: path::PathBuf) -> Status {
+        Status {
+            kind,
+            from_file: file,
+            to_file: path::PathBuf::new(),
+        }
+    }
+    /// a file renamed or copied from `from`
+    pub fn renamed(kind: StatusKind, from: path::PathBuf, file: path::PathBuf) -> Status {
+        Status {
+            kind,
+            from_file: from,
+            to_file: file,
+        }
+    }
+    pub fn kind(&self) -> StatusKind {
+        self.kind
+    }
+    /// the change staged in the index, unmodified unless the file is
+    /// tracked
+    pub fn index(&self) -> ChangeKind {
+        match self.kind
+        {
+            StatusKind::Tracked { index, .. } => index,
+            _ => ChangeKind::Unmodified,
+        }
+    }
+    /// the change in the working tree, unmodified unless the file is
+    /// tracked
+    pub fn tree(&self) -> ChangeKind {
+        match self.kind
+        {
+            StatusKind::Tracked { tree, .. } => tree,
+            _ => ChangeKind::Unmodified,
+        }
+    }
     pub fn is_unmerged(&self) -> bool {
-        self.index == 'U' || self.tree == 'U'
+        matches!(self.kind, StatusKind::Unmerged { .. })
+    }
+    pub fn is_untracked(&self) -> bool {
+        self.kind == StatusKind::Untracked
     }
-    pub fn file(&self) -> String {
-        if self.to_file.is_empty()
+    /// the source of a rename or copy
+    pub fn source(&self) -> Option<&path::Path> {
+        if is_empty(&self.to_file) { None } else { Some(&self.from_file) }
+    }
+    /// `Some` if `Repository::add` won't stage the file
+    pub fn skip(&self) -> Option<Skip> {
+        if self.is_unmerged()
         {
-            debug!("form file ({})", self.to_file.len());
-            self.from_file.clone()
+            Some(Skip::Unmerged)
         }
         else
         {
-            debug!("from file");
-            self.to_file.clone()
+            None
+        }
+    }
+    pub fn file(&self) -> &path::Path {
+        if is_empty(&self.to_file)
+        {
+            &self.from_file
+        }
+        else
+        {
+            &self.to_file
         }
     }
 }
 
 impl fmt::Debug for Status {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.to_file.is_empty()
+        let (index, tree) = self.kind.flags();
+        if is_empty(&self.to_file)
         {
-            write!(f, "{}{} {}", self.index, self.tree, self.from_file)
+            write!(f, "{}{} {}", index, tree, paths::escape(&self.from_file))
         }
         else
         {
             write!(
                 f,
                 "{}{} {} -> {}",
-                self.index, self.tree, self.from_file, self.to_file
+                index,
+                tree,
+                paths::escape(&self.from_file),
+                paths::escape(&self.to_file)
             )
         }
     }
@@ -413,18 +1153,19 @@ impl fmt::Debug for Status {
 
 impl fmt::Display for Status {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.to_file.is_empty()
+        let flag = encode_status_flag(self.kind);
+        if is_empty(&self.to_file)
         {
-            write!(f, "  {} {}", encode_status_flag(self.index), self.from_file)
+            write!(f, "  {} {}", flag, paths::escape(&self.from_file))
         }
         else
         {
             write!(
                 f,
                 "  {} {} -> {}",
-                encode_status_flag(self.index),
-                self.from_file,
-                self.to_file
+                flag,
+                paths::escape(&self.from_file),
+                paths::escape(&self.to_file)
             )
         }
     }
@@ -449,16 +1190,341 @@ pub fn create_commit_message(
     String::from_utf8(commitmsg)
 }
 
-fn encode_status_flag(flag: char) -> char {
-    match flag
+fn encode_status_flag(kind: StatusKind) -> char {
+    let change = match kind
+    {
+        // unstaged changes show the flag they get once staged
+        StatusKind::Tracked { index: ChangeKind::Unmodified, tree } => tree,
+        StatusKind::Tracked { index, .. } => index,
+        StatusKind::Untracked => return '?',
+        StatusKind::Ignored => return '•',
+        StatusKind::Unmerged { .. } => return '!',
+    };
+    match change
     {
-        'M' => '~',
-        'A' => '+',
-        'D' => '-',
-        'R' => '>',
-        'U' => '!',
-        '?' => '?',
-        ' ' => ' ',
-        _ => '•',
+        ChangeKind::Modified | ChangeKind::TypeChanged => '~',
+        ChangeKind::Added | ChangeKind::Copied => '+',
+        ChangeKind::Deleted => '-',
+        ChangeKind::Renamed => '>',
+        ChangeKind::Unmodified => ' ',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repository(mock: &Mock) -> Repository {
+        Repository::from(".", "test")
+            .unwrap()
+            .with_backend(Box::new(mock.clone()))
+    }
+
+    const SYNC: [&str; 6] = [
+        "add a.txt b.txt",
+        "commit",
+        "pull origin rebase",
+        "submodule init",
+        "submodule update",
+        "push origin",
+    ];
+
+    #[test]
+    fn test_sync() {
+        let mock = Mock::new().with_status(" M a.txt\0?? b.txt\0");
+        repository(&mock).sync().unwrap();
+        assert_eq!(mock.calls(), SYNC);
+    }
+
+    #[test]
+    fn test_sync_nothing_staged() {
+        let mock = Mock::new().with_dirty_submodule("sub");
+        assert_eq!(repository(&mock).sync().unwrap(), Synced::Done);
+        let calls = ["add sub", "pull origin rebase", "submodule init", "submodule update", "push origin"];
+        assert_eq!(mock.calls(), calls);
+    }
+
+    #[test]
+    fn test_poll() {
+        let mock = Mock::new().without_upstream();
+        assert_eq!(repository(&mock).poll().unwrap(), Synced::Done);
+        assert_eq!(mock.calls(), ["submodule init", "submodule update", "push --set-upstream origin"]);
+        let mock = Mock::new().with_status(" M a.txt\0?? b.txt\0");
+        repository(&mock).poll().unwrap();
+        assert_eq!(mock.calls(), SYNC);
+    }
+
+    #[test]
+    fn test_rename_order() {
+        let mock = Mock::new().with_status("R  new.txt\0old.txt\0");
+        let status = repository(&mock).status().unwrap();
+        assert_eq!(status[0].file(), path::Path::new("new.txt"));
+        assert_eq!(status[0].to_string(), "  > old.txt -> new.txt");
+    }
+
+    #[test]
+    fn test_status_kind() {
+        let mock = Mock::new().with_status(" M a.txt\0A  b.txt\0?? c.txt\0DU d.txt\0UU e.txt\0");
+        let status = repository(&mock).status().unwrap();
+        let kinds: Vec<StatusKind> = status.iter().map(|s| s.kind()).collect();
+        assert_eq!(
+            kinds,
+            [
+                StatusKind::Tracked {
+                    index: ChangeKind::Unmodified,
+                    tree: ChangeKind::Modified
+                },
+                StatusKind::Tracked {
+                    index: ChangeKind::Added,
+                    tree: ChangeKind::Unmodified
+                },
+                StatusKind::Untracked,
+                StatusKind::Unmerged {
+                    conflict: Conflict::DeletedByUs
+                },
+                StatusKind::Unmerged {
+                    conflict: Conflict::BothModified
+                },
+            ]
+        );
+        for s in &status
+        {
+            let (index, tree) = s.kind().flags();
+            assert_eq!(StatusKind::from_flags(index, tree), Some(s.kind()));
+        }
+        assert!(!Conflict::DeletedByUs.has_ours() && Conflict::DeletedByUs.has_theirs());
+
+        let renamed = Mock::new().with_status("R  new.txt\0old.txt\0").status().unwrap();
+        let toml = toml::to_string(&*renamed[0]).unwrap();
+        assert_eq!(toml, "state = \"tracked\"\nindex = \"renamed\"\ntree = \"unmodified\"\nfrom_file = \"old.txt\"\nto_file = \"new.txt\"\n");
+        let back: Status = toml::from_str(&toml).unwrap();
+        assert!(back == *renamed[0]);
+        assert_eq!(back.source(), Some(path::Path::new("old.txt")));
+    }
+
+    #[test]
+    fn test_add_deletions_and_renames() {
+        let mock = Mock::new().with_status(" D gone.txt\0D  staged.txt\0RM new.txt\0old.txt\0?? b.txt\0 R moved\0was\0");
+        let repo = repository(&mock);
+        let staged = repo.add(repo.status().unwrap()).unwrap();
+        assert_eq!(staged.len(), 5);
+        assert_eq!(mock.calls(), ["add new.txt b.txt moved", "rm gone.txt was"]);
+
+        let msg = create_commit_message(&repo.status().unwrap(), "test").unwrap();
+        let lines: Vec<&str> = msg.lines().skip(2).collect();
+        assert_eq!(lines, ["  - gone.txt", "  - staged.txt", "  > old.txt -> new.txt", "  + b.txt", "  > was -> moved"]);
+    }
+
+    #[test]
+    fn test_commit() {
+        let mock = Mock::new().with_status(" M a.txt\0");
+        let repo = repository(&mock);
+        match repo.commit(repo.status().unwrap())
+        {
+            Err(GifsyError::CommitFailed(_)) => (),
+            r => panic!("nothing staged but commit returned {:?}", r),
+        }
+        repo.add(repo.status().unwrap()).unwrap();
+        let id = repo.commit(repo.status().unwrap()).unwrap();
+        assert_eq!(id, format!("{:040x}", 1));
+    }
+
+    #[test]
+    fn test_sync_clean() {
+        let mock = Mock::new();
+        let repo = repository(&mock).with_pull_strategy(PullStrategy::Merge);
+        repo.sync().unwrap();
+        assert_eq!(
+            mock.calls(),
+            ["pull origin merge", "submodule init", "submodule update", "push origin"]
+        );
+    }
+
+    #[test]
+    fn test_sync_failures() {
+        let steps = [
+            ("status", 0),
+            ("add", 1),
+            ("commit", 2),
+            ("pull", 3),
+            ("submodules_init", 4),
+            ("submodules_update", 5),
+            ("push", 6),
+        ];
+        for &(op, calls) in &steps
+        {
+            let mock = Mock::new().with_status(" M a.txt\0?? b.txt\0");
+            mock.fail(op, GifsyError::CmdFail(128, op.to_string()));
+            match repository(&mock).sync()
+            {
+                Err(GifsyError::CmdFail(128, ref m)) if m == op => (),
+                r => panic!("{} failed with {:?}", op, r),
+            }
+            assert_eq!(mock.calls(), &SYNC[..calls], "failing {}", op);
+        }
+    }
+
+    #[test]
+    fn test_sync_push_rejected() {
+        let mock = Mock::new().with_status(" M a.txt\0?? b.txt\0");
+        let rejected = || GifsyError::Rejected("(fetch first)".to_string());
+        mock.fail("push", rejected());
+        mock.fail("push", rejected());
+        let repo = repository(&mock).with_push_retries(2, Duration::from_millis(1));
+        repo.sync().unwrap();
+        let mut calls = SYNC.to_vec();
+        calls.extend(&["pull origin rebase", "push origin", "pull origin rebase", "push origin"]);
+        assert_eq!(mock.calls(), calls);
+
+        let mock = Mock::new();
+        for _ in 0..3
+        {
+            mock.fail("push", rejected());
+        }
+        let repo = repository(&mock).with_push_retries(2, Duration::from_millis(1));
+        match repo.sync()
+        {
+            Err(GifsyError::Rejected(_)) => (),
+            r => panic!("the race persists but sync returned {:?}", r),
+        }
+        assert_eq!(mock.calls().iter().filter(|c| c.starts_with("push")).count(), 3);
+    }
+
+    #[test]
+    fn test_sync_offline() {
+        for &(op, calls) in &[("pull", 3), ("push", 6)]
+        {
+            let mock = Mock::new().with_status(" M a.txt\0?? b.txt\0");
+            mock.fail(op, GifsyError::Offline("Could not resolve host".to_string()));
+            assert_eq!(repository(&mock).sync().unwrap(), Synced::Offline(1));
+            assert_eq!(mock.calls(), &SYNC[..calls], "{} offline", op);
+        }
+    }
+
+    #[test]
+    fn test_offline_without_upstream() {
+        let unknown = || GifsyError::CmdFail(128, "unknown revision".to_string());
+        for &(count_fails, unpushed) in &[(false, 1), (true, 0)]
+        {
+            let mock = Mock::new().without_upstream().with_status(" M a.txt\0");
+            mock.fail("push", GifsyError::Offline("Could not resolve host".to_string()));
+            mock.fail("log", unknown());
+            if count_fails
+            {
+                mock.fail("count", unknown());
+            }
+            assert_eq!(repository(&mock).sync().unwrap(), Synced::Offline(unpushed));
+        }
+    }
+
+    #[test]
+    fn test_last_sync() {
+        let mock = Mock::new().with_status(" M a.txt\0");
+        let repo = repository(&mock);
+        assert_eq!(repo.last_sync().unwrap(), None);
+        repo.sync().unwrap();
+        assert_eq!(repo.last_sync().unwrap().unwrap().outcome, Outcome::Done);
+        mock.fail("pull", GifsyError::Offline("Could not resolve host".to_string()));
+        repo.sync().unwrap();
+        let last = repo.last_sync().unwrap().unwrap();
+        assert_eq!((last.outcome, last.message.as_str()), (Outcome::Offline, "0 commits not pushed yet"));
+        mock.fail("push", GifsyError::Diverged);
+        assert!(repo.sync().is_err());
+        assert_eq!(repo.last_sync().unwrap().unwrap().outcome, Outcome::Failed);
+    }
+
+    #[test]
+    fn test_sync_interrupted() {
+        let mock = Mock::new();
+        mock.conflict();
+        let repo = repository(&mock);
+        match repo.sync()
+        {
+            Err(GifsyError::Interrupted(Interrupted::Rebase)) => (),
+            r => panic!("pull not interrupted {:?}", r),
+        }
+        // nothing happens until the rebase is recovered
+        let calls = mock.calls().len();
+        assert!(matches!(repo.sync(), Err(GifsyError::Interrupted(_))));
+        assert_eq!(mock.calls().len(), calls);
+        assert_eq!(repo.recover_abort().unwrap(), Some(Interrupted::Rebase));
+        repo.sync().unwrap();
+        assert_eq!(mock.calls().last().unwrap(), "push origin");
+    }
+
+    #[test]
+    fn test_sync_first_push() {
+        let mock = Mock::new().with_status("?? a.txt\0").without_upstream();
+        repository(&mock).sync().unwrap();
+        assert_eq!(
+            mock.calls(),
+            ["add a.txt", "commit", "submodule init", "submodule update", "push --set-upstream origin"]
+        );
+    }
+
+    #[test]
+    fn test_sync_wrong_branch() {
+        let mock = Mock::new().with_status(" M a.txt\0");
+        let repo = repository(&mock).with_remote("origin", Some("notes"));
+        match repo.sync()
+        {
+            Err(GifsyError::WrongBranch(ref expected, ref found)) =>
+            {
+                assert_eq!((expected.as_str(), found.as_str()), ("notes", "main"))
+            }
+            r => panic!("synced the wrong branch {:?}", r),
+        }
+        assert!(mock.calls().is_empty());
+
+        let mock = Mock::new().with_branch(None);
+        assert!(matches!(repository(&mock).sync(), Err(GifsyError::DetachedHead)));
+        assert!(mock.calls().is_empty());
+    }
+
+    #[test]
+    fn test_sync_conflict_strategy() {
+        let mock = Mock::new().with_status("UU a.txt\0UU b.txt\0");
+        let rules = vec![ConflictRule::new("b.*", ConflictStrategy::Theirs).unwrap()];
+        let repo = repository(&mock).with_conflicts(ConflictStrategy::Manual, rules);
+        repo.sync().unwrap();
+        assert_eq!(mock.calls()[..3], ["checkout Theirs b.txt", "add b.txt", "commit"]);
+
+        // sides without a stage are removed instead of checked out
+        let mock = Mock::new().with_status("DD a.txt\0AU b.txt\0UA c.txt\0AA d.txt\0");
+        let repo = repository(&mock).with_conflicts(ConflictStrategy::Theirs, Vec::new());
+        repo.sync().unwrap();
+        assert_eq!(
+            mock.calls()[..7],
+            ["rm a.txt", "rm b.txt", "checkout Theirs c.txt", "add c.txt", "checkout Theirs d.txt", "add d.txt", "commit"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_rebase_sides() {
+        let mock = Mock::new().with_status(" M a.txt\0");
+        mock.conflict();
+        let rules = vec![ConflictRule::new("b.*", ConflictStrategy::Remote).unwrap()];
+        let repo = repository(&mock).with_conflicts(ConflictStrategy::Local, rules);
+        assert!(repo.sync().is_err());
+        // the rebase stops with the remote version as git's ours
+        let mock = mock.with_status("UU a.txt\0UU b.txt\0");
+        let n = mock.calls().len();
+        repo.recover_continue().unwrap();
+        assert_eq!(
+            mock.calls()[n..],
+            ["checkout Theirs a.txt", "add a.txt", "checkout Ours b.txt", "add b.txt", "resume rebase in progress"]
+        );
+    }
+
+    #[test]
+    fn test_ff_only_pull_failure() {
+        let mock = Mock::new();
+        let repo = repository(&mock).with_pull_strategy(PullStrategy::FfOnly);
+        mock.fail("pull", GifsyError::CmdFail(1, "not possible to fast-forward".to_string()));
+        mock.fail("fetch", GifsyError::CmdFail(128, "network down".to_string()));
+        assert!(matches!(repo.pull(), Err(GifsyError::CmdFail(1, _))));
+        mock.fail("pull", GifsyError::CmdFail(1, "not possible to fast-forward".to_string()));
+        mock.fail("is_ancestor", GifsyError::CmdFail(128, "bad revision".to_string()));
+        assert!(matches!(repo.pull(), Err(GifsyError::CmdFail(1, _))));
     }
 }
@@ -1,5 +1,9 @@
 use super::*;
-use std::str;
+use std::cmp;
+use std::fmt;
+use std::mem;
+use std::path::PathBuf;
+use std::str::{self, FromStr};
 
 #[macro_export]
 macro_rules! parsers {
@@ -14,23 +18,208 @@ macro_rules! parsers {
     };
 }
 
-pub type ParserFn<'a> = fn(&'a str, &mut Status) -> Result<Option<&'a str>,&'a str>;
+/// the part of a record a parser failed at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    IndexFlag,
+    TreeFlag,
+    Path,
+    /// the source of a rename or a copy
+    RenameSource,
+    Oid,
+    Ref,
+    Author,
+    Time,
+    Subject,
+    /// the added or deleted lines of a file
+    Count,
+    /// the state letter of a submodule
+    State,
+    /// the letter telling the kind of a record
+    Kind,
+    Header,
+    /// the submodule flags of a porcelain v2 record
+    Submodule,
+    Mode,
+    /// the similarity of a rename or a copy
+    Score,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self
+        {
+            Field::IndexFlag => write!(f, "index flag"),
+            Field::TreeFlag => write!(f, "tree flag"),
+            Field::Path => write!(f, "path"),
+            Field::RenameSource => write!(f, "rename source"),
+            Field::Oid => write!(f, "object id"),
+            Field::Ref => write!(f, "ref"),
+            Field::Author => write!(f, "author"),
+            Field::Time => write!(f, "time"),
+            Field::Subject => write!(f, "subject"),
+            Field::Count => write!(f, "line count"),
+            Field::State => write!(f, "state"),
+            Field::Kind => write!(f, "record kind"),
+            Field::Header => write!(f, "header"),
+            Field::Submodule => write!(f, "submodule flags"),
+            Field::Mode => write!(f, "mode"),
+            Field::Score => write!(f, "score"),
+        }
+    }
+}
+
+/// the bytes of the input shown around a parser error
+const EXCERPT: usize = 24;
+
+/// Where and why parsing the output of git failed. A parser only sees
+/// the rest of the input, so it records how much was left and `parse` or
+/// `many` turn that into the offset and the excerpt once they are done.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// of the failing field from the start of the input
+    pub offset: usize,
+    pub field: Field,
+    /// the input from the offset on, non-printable bytes escaped
+    pub excerpt: String,
+    /// the bytes of the input left at the failing field
+    left: usize,
+}
+
+impl ParseError {
+    /// `field` at the start of `rest` is invalid
+    pub fn at(field: Field, rest: &[u8]) -> ParseError {
+        ParseError {
+            offset: 0,
+            field,
+            excerpt: String::new(),
+            left: rest.len(),
+        }
+    }
+    /// takes the offset and the excerpt from the whole `input`
+    pub fn within(mut self, input: &[u8]) -> ParseError {
+        let start = input.len().saturating_sub(self.left);
+        let end = cmp::min(start + EXCERPT, input.len());
+        self.offset = start;
+        self.excerpt = input[start..end].escape_ascii().to_string();
+        if end < input.len()
+        {
+            self.excerpt.push_str("...");
+        }
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid {} at byte {} \"{}\"", self.field, self.offset, self.excerpt)
+    }
+}
+
+/// what a parser took from the input and the input after it
+pub type Parsed<'a, T> = Result<(T, &'a [u8]), ParseError>;
 
-pub fn parse<'a, F>(sstr: &'a str, parsers: Vec<ParserFn<'a>> ) ->
-    Result<Vec<Box<Status>>,&'a str>
+/// applies `parser` until the input is used up, the records of most git
+/// outputs follow each other without a header
+pub fn many<'a, T, F>(input: &'a [u8], mut parser: F) -> Result<Vec<T>, ParseError>
+where
+    F: FnMut(&'a [u8]) -> Parsed<'a, T>,
+{
+    let mut records = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty()
+    {
+        let (record, r) = parser(rest).map_err(|e| e.within(input))?;
+        if r.len() == rest.len()
+        {
+            // a parser taking nothing would never get to the end
+            return Err(ParseError::at(Field::Path, rest).within(input));
+        }
+        records.push(record);
+        rest = r;
+    }
+    Ok(records)
+}
+
+/// the bytes up to `end`, which is skipped
+pub fn until<'a>(s: &'a [u8], end: u8, field: Field) -> Parsed<'a, &'a [u8]> {
+    match s.iter().position(|&b| b == end)
+    {
+        Some(pos) => Ok((&s[..pos], &s[pos + 1..])),
+        None => Err(ParseError::at(field, s)),
+    }
+}
+
+/// the bytes up to the next space of a record ending with a zero-byte,
+/// the space is skipped
+pub fn word<'a>(s: &'a [u8], field: Field) -> Parsed<'a, &'a [u8]> {
+    match s.iter().position(|&b| b == b' ' || b == TERMINATOR)
+    {
+        Some(pos) if s[pos] == b' ' => Ok((&s[..pos], &s[pos + 1..])),
+        _ => Err(ParseError::at(field, s)),
+    }
+}
+
+/// the text up to `end`, bytes that aren't UTF-8 are replaced
+pub fn text<'a>(s: &'a [u8], end: u8, field: Field) -> Parsed<'a, String> {
+    let (t, rest) = until(s, end, field)?;
+    Ok((String::from_utf8_lossy(t).into_owned(), rest))
+}
+
+/// the decimal number up to `end`
+pub fn number<'a, T: FromStr>(s: &'a [u8], end: u8, field: Field) -> Parsed<'a, T> {
+    let (n, rest) = until(s, end, field)?;
+    match str::from_utf8(n).ok().and_then(|n| n.parse().ok())
+    {
+        Some(n) => Ok((n, rest)),
+        None => Err(ParseError::at(field, s)),
+    }
+}
+
+/// skips `tag`
+pub fn tag<'a>(s: &'a [u8], tag: &[u8], field: Field) -> Parsed<'a, ()> {
+    match s.strip_prefix(tag)
+    {
+        Some(rest) => Ok(((), rest)),
+        None => Err(ParseError::at(field, s)),
+    }
+}
+
+/// one of the ASCII characters of `charset`
+pub fn one_of<'a>(s: &'a [u8], charset: &'static str, field: Field) -> Parsed<'a, char> {
+    parse_char(s, charset).ok_or_else(|| ParseError::at(field, s))
+}
+
+/// the shortest abbreviation of an object id git prints
+const MIN_OID: usize = 4;
+
+/// a full or an abbreviated object id
+pub fn oid<'a>(s: &'a [u8], field: Field) -> Parsed<'a, String> {
+    let len = s.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+    if len < MIN_OID
+    {
+        return Err(ParseError::at(field, s));
+    }
+    Ok((String::from_utf8_lossy(&s[..len]).into_owned(), &s[len..]))
+}
+
+/// parsers work on the bytes git prints, file names need not be UTF-8
+pub type ParserFn<'a> = fn(&'a [u8], &mut Status) -> Result<Option<&'a [u8]>, ParseError>;
+
+/// the status records of `git status --porcelain -z`, each one taken by
+/// `parsers` in turn
+pub fn parse<'a, F>(sstr: &'a [u8], parsers: Vec<ParserFn<'a>> ) ->
+    Result<Vec<Box<Status>>, ParseError>
 {
     let mut s: Vec<Box<Status>> = Vec::new();
-    let mut rest: &'a str = sstr;
+    let mut rest: &'a [u8] = sstr;
     while rest.len() > 1 {
-        let mut status: Box<Status> =
-            Box::new(Status{index: '\0',
-                            tree: '\0',
-                            from_file: "".to_string(),
-                            to_file: "".to_string()});
+        let mut status: Box<Status> = Box::new(Status::new(StatusKind::Untracked, PathBuf::new()));
         for p in &parsers {
-            rest = match p(rest, status.as_mut())? {
-                Some(r) => r,
-                None => return Err("")
+            rest = match p(rest, status.as_mut()) {
+                Ok(Some(r)) => r,
+                Ok(None) => return Err(ParseError::at(Field::Path, &[]).within(sstr)),
+                Err(e) => return Err(e.within(sstr)),
             }
         }
         s.push(status);
@@ -38,79 +227,75 @@ pub fn parse<'a, F>(sstr: &'a str, parsers: Vec<ParserFn<'a>> ) ->
     Ok(s)
 }
 
-pub fn parse_index<'a>(s: &'a str, status: &mut Status) -> Result<Option<&'a str>, &'a str> {
-     match parse_utf8_char(s, "MADRU? ") {
-        Some((c, cs)) => {status.index = c;
-                          Ok(Some(cs))} ,
-        None => Err(""),
-    }
-}
+/// the letters `git status --porcelain` uses for the index and the
+/// working tree
+const FLAGS: &str = " MTADRCU?!";
 
-pub fn parse_tree<'a>(s: &'a str, status: &mut Status) -> Result<Option<&'a str>,&'a str> {
-    match parse_utf8_char(s, "MADU? ") {
-        Some((c, cs)) => {status.tree = c;
-                          Ok(Some(cs))} ,
-        None => Err("")
-    }
+/// the two letters of the index and the working tree, conflicts need
+/// both to be told apart
+pub fn parse_flags<'a>(s: &'a [u8], status: &mut Status) -> Result<Option<&'a [u8]>, ParseError> {
+    let (index, rest) = one_of(s, FLAGS, Field::IndexFlag)?;
+    let (tree, rest) = one_of(rest, FLAGS, Field::TreeFlag)?;
+    // a known letter can still not go with the other one, like `U?`
+    status.kind = StatusKind::from_flags(index, tree).ok_or_else(|| ParseError::at(Field::TreeFlag, &s[1..]))?;
+    Ok(Some(rest))
 }
 
-pub fn parse_from<'a>(s: &'a str, status: &mut Status) -> Result<Option<&'a str>, &'a str> {
+/// the file after the space following the flags, it is taken as is as
+/// names may start or end with spaces
+pub fn parse_from<'a>(s: &'a [u8], status: &mut Status) -> Result<Option<&'a [u8]>, ParseError> {
+    let ((), s) = tag(s, b" ", Field::Path)?;
     let (file, rest) = parse_c_string(s)?;
-    status.from_file = file.trim().to_string();
+    status.from_file = paths::from_bytes(file);
     Ok(rest)
 }
 
-pub fn parse_to<'a>(s: &'a str, status: &mut Status) -> Result<Option<&'a str>, &'a str> {
-    if status.index == 'R' {
-        let (f, rest) = parse_c_string(s)?;
-        status.to_file = f.trim().to_string();
-        Ok(rest)
+pub fn parse_to<'a>(s: &'a [u8], status: &mut Status) -> Result<Option<&'a [u8]>, ParseError> {
+    let moved = |c: ChangeKind| c == ChangeKind::Renamed || c == ChangeKind::Copied;
+    if moved(status.index()) || moved(status.tree()) {
+        // with -z the destination comes first, the source after it
+        let (f, rest) = until(s, TERMINATOR, Field::RenameSource)?;
+        status.to_file = mem::replace(&mut status.from_file, paths::from_bytes(f));
+        Ok(Some(rest))
     } else {
         Ok(Some(s))
     }
 }
 
-pub fn parse_utf8_char<'a>(s: &'a str, charset: &'static str) -> Option<(char, &'a str)> {
-    let mut cs = s.chars();
-
-    match cs.next() {
-        Some(c) => if charset.chars().any(|x| x == c) {
-            Some((c, cs.as_str()))
-            } else {
-                None
-        },
+/// the first byte of `s` if it is one of the ASCII characters of
+/// `charset`
+pub fn parse_char<'a>(s: &'a [u8], charset: &'static str) -> Option<(char, &'a [u8])> {
+    match s.split_first() {
+        Some((&b, rest)) if b.is_ascii() && charset.contains(b as char) => Some((b as char, rest)),
         _ => None
     }
 }
 
-const TERMINATOR: char = '\u{0}';
-
-/// take any character until it meets a zero-byte or the end and
-/// returns the found string and the rest of the string
-pub fn parse_c_string(stream: &str) -> Result<(&str, Option<&str>), &str> {
-
-    let pos = match stream.chars().position(|c| c == TERMINATOR) {
-        Some(pos) => pos,
-        None => return Err(""),
-    };
+const TERMINATOR: u8 = 0;
 
-    Ok((&stream[0..pos], Some(&stream[(pos+1)..])))
+/// take any byte until it meets a zero-byte and returns the found bytes
+/// and the rest of the stream, it fails at the start of a path without
+/// its zero-byte
+pub fn parse_c_string(stream: &[u8]) -> Result<(&[u8], Option<&[u8]>), ParseError> {
+    let (s, rest) = until(stream, TERMINATOR, Field::Path)?;
+    Ok((s, Some(rest)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::Path;
 
     #[test]
-    fn test_parse_utf8_char() {
-        let input = " A demo\u{0}";
+    fn test_parse_char() {
+        let input = b" A demo\0";
 
-        match parse_utf8_char(input, "MADRU ") {
-            Some((c, rest)) => {assert!(rest == "A demo\u{0}"); assert!(c == ' ');},
+        match parse_char(input, "MADRU ") {
+            Some((c, rest)) => {assert!(rest == b"A demo\0"); assert!(c == ' ');},
             None => assert!(false)
         };
 
-        match parse_utf8_char(input, "MADRU") {
+        match parse_char(input, "MADRU") {
             Some(_) => assert!(false),
             None => assert!(true)
         };
@@ -118,37 +303,125 @@ mod tests {
 
     #[test]
     fn test_match_til_zero() {
-        let input = "demo\u{0}second\u{0}";
+        let input = b"demo\0second\0";
 
         let (f, rest) = match parse_c_string(input){
             Ok((f, Some(rest))) => (f,rest),
-            _ => ("",""),
+            _ => (&b""[..],&b""[..]),
         };
         println!("{:?} {:?}", f, rest);
-        assert!(f == "demo");
-        assert!(rest == "second\u{0}");
+        assert!(f == b"demo");
+        assert!(rest == b"second\0");
     }
 
     #[test]
     fn test_parse_file_single() {
-        let input = "demo\u{0}";
+        let input = b"demo\0";
 
         let (f, rest) = match parse_c_string(input){
             Ok((f, Some(rest))) => (f,rest),
-            _ => ("",""),
+            _ => (&b""[..],&b""[..]),
         };
         println!("{:?} {:?}", f, rest);
-        assert!(f == "demo");
-        assert!(rest == "");
+        assert!(f == b"demo");
+        assert!(rest == b"");
+    }
+
+    #[test]
+    fn test_match_til_zero_multibyte() {
+        let input = "grüße.txt\u{0}ä\u{0}".as_bytes();
+
+        match parse_c_string(input) {
+            Ok((f, Some(rest))) => {assert_eq!(f, "grüße.txt".as_bytes()); assert_eq!(rest, "ä\u{0}".as_bytes());},
+            r => panic!("multibyte file not parsed {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_parse_non_utf8() {
+        let status = parse_v1(b"?? gr\xfc\xdfe.txt\0R   lead\0trail \0");
+        assert_eq!(paths::as_bytes(status[0].file()), b"gr\xfc\xdfe.txt");
+        assert_eq!(status[0].to_string(), "  ? \"gr\\374\\337e.txt\"");
+        // spaces are part of the names
+        assert_eq!(status[1].file(), Path::new(" lead"));
+        assert_eq!(status[1].source(), Some(Path::new("trail ")));
+    }
+
+    fn parse_v1(input: &[u8]) -> Vec<Status> {
+        let p = parsers![parse_flags, parse_from, parse_to];
+        let status = parse::<Vec<&Status>>(input, p).expect("status not parsed");
+        status.into_iter().map(|s| *s).collect()
+    }
+
+    #[test]
+    fn test_parse_all_flags() {
+        let tracked = [" M", " T", " D", "M ", "MM", "MT", "MD", "T ", "TM", "A ", "AM", "AT", "AD",
+                       "D ", "R ", "RM", "RT", "RD", "C ", "CM", "CT", "CD"];
+        for xy in &tracked {
+            // renames and copies have their source after the file
+            let source = if xy.starts_with(['R', 'C']) { "source\u{0}" } else { "" };
+            let status = parse_v1(format!("{} file\u{0}{}", xy, source).as_bytes());
+            let (index, tree) = status[0].kind().flags();
+            assert_eq!(format!("{}{}", index, tree), *xy);
+            assert_eq!(status[0].file(), Path::new("file"));
+        }
+        let unmerged = [("DD", Conflict::BothDeleted), ("AU", Conflict::AddedByUs),
+                        ("UD", Conflict::DeletedByThem), ("UA", Conflict::AddedByThem),
+                        ("DU", Conflict::DeletedByUs), ("AA", Conflict::BothAdded),
+                        ("UU", Conflict::BothModified)];
+        for &(xy, conflict) in &unmerged {
+            let status = parse_v1(format!("{} file\u{0}", xy).as_bytes());
+            assert_eq!(status[0].kind(), StatusKind::Unmerged { conflict });
+            assert!(status[0].is_unmerged());
+        }
+        assert_eq!(parse_v1(b"?? new\0")[0].kind(), StatusKind::Untracked);
+        assert_eq!(parse_v1(b"!! build/\0")[0].kind(), StatusKind::Ignored);
+
+        let p = parsers![parse_flags, parse_from, parse_to];
+        assert!(parse::<Vec<&Status>>(b"XY file\0", p).is_err());
+    }
+
+    #[test]
+    fn test_parse_rename_and_copy() {
+        let status = parse_v1(b"R  new.txt\0old.txt\0C  copy.txt\0orig.txt\0 R moved\0was\0M  next.txt\0");
+        let paths: Vec<(Option<&Path>, &Path)> = status.iter().map(|s| (s.source(), s.file())).collect();
+        let path = Path::new;
+        assert_eq!(paths, [(Some(path("old.txt")), path("new.txt")),
+                           (Some(path("orig.txt")), path("copy.txt")),
+                           (Some(path("was")), path("moved")),
+                           (None, path("next.txt"))]);
+        assert_eq!(status[1].index(), ChangeKind::Copied);
+        assert_eq!(status[2].tree(), ChangeKind::Renamed);
     }
 
     #[test]
     fn test_parse_file_fail() {
-        let input = "demo";
+        let input = b"demo";
 
         let (_, _) = match parse_c_string(input){
             Ok((f, Some(rest))) => (f,rest),
-            _ => ("",""),
+            _ => (&b""[..],&b""[..]),
+        };
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |input: &[u8]| {
+            let p = parsers![parse_flags, parse_from, parse_to];
+            parse::<Vec<&Status>>(input, p).unwrap_err()
         };
+        let e = error(b" M ok\0XM bad\0");
+        assert_eq!((e.field, e.offset), (Field::IndexFlag, 6));
+        assert_eq!(e.to_string(), "invalid index flag at byte 6 \"XM bad\\x00\"");
+        assert_eq!(error(b"MX ok\0").field, Field::TreeFlag);
+        assert_eq!(error(b"U? ok\0").field, Field::TreeFlag);
+        let e = error(b" Mno-space\0");
+        assert_eq!((e.field, e.offset), (Field::Path, 2));
+        let e = error(b" M unterminated");
+        assert_eq!((e.field, e.offset, e.excerpt.as_str()), (Field::Path, 3, "unterminated"));
+        let e = error(b"R  new\0old");
+        assert_eq!((e.field, e.offset), (Field::RenameSource, 7));
+        let e = error(&[&[b'a'; 40][..], b"\0"].concat());
+        assert_eq!(e.excerpt, format!("{}...", "a".repeat(EXCERPT)));
     }
 }
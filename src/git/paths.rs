@@ -0,0 +1,55 @@
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// a path git printed, file names are bytes and need not be UTF-8
+pub fn from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+/// the bytes of `path` as git expects them
+pub fn as_bytes(path: &Path) -> &[u8] {
+    path.as_os_str().as_bytes()
+}
+
+/// `path` for messages and notifications, a name that isn't UTF-8 is
+/// quoted like git does with its invalid bytes as octal escapes
+pub fn escape(path: &Path) -> Cow<'_, str> {
+    if let Some(s) = path.to_str()
+    {
+        return Cow::Borrowed(s);
+    }
+    let mut escaped = String::from("\"");
+    for chunk in as_bytes(path).utf8_chunks()
+    {
+        for c in chunk.valid().chars()
+        {
+            match c
+            {
+                '"' | '\\' => escaped.extend(['\\', c]),
+                c => escaped.push(c),
+            }
+        }
+        for b in chunk.invalid()
+        {
+            let _ = write!(escaped, "\\{:03o}", b);
+        }
+    }
+    escaped.push('"');
+    Cow::Owned(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(Path::new("grüße.txt")), "grüße.txt");
+        let latin1 = from_bytes(b"gr\xfc\xdfe \"1\".txt");
+        assert_eq!(escape(&latin1), "\"gr\\374\\337e \\\"1\\\".txt\"");
+        assert_eq!(as_bytes(&latin1), b"gr\xfc\xdfe \"1\".txt");
+    }
+}
@@ -0,0 +1,199 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+
+use super::GifsyError;
+
+/// how often a running git is checked for its end, a timeout or a
+/// cancellation
+const POLL: Duration = Duration::from_millis(50);
+/// how long git may clean up after SIGTERM before it gets killed
+const GRACE: Duration = Duration::from_secs(5);
+
+/// how long the git operations may take before they are stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// fetching and listing the remote refs
+    pub fetch: Duration,
+    pub pull: Duration,
+    pub push: Duration,
+    /// initializing and updating the submodules
+    pub submodules: Duration,
+    /// every operation not talking to a remote, like add or commit
+    pub local: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            fetch: Duration::from_secs(300),
+            pull: Duration::from_secs(300),
+            push: Duration::from_secs(300),
+            submodules: Duration::from_secs(600),
+            local: Duration::from_secs(300),
+        }
+    }
+}
+
+fn cancelled_flag() -> &'static Arc<AtomicBool> {
+    static CANCELLED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+    CANCELLED.get_or_init(|| Arc::new(AtomicBool::new(false)))
+}
+
+/// SIGINT and SIGTERM stop the running git and fail every further
+/// operation with `GifsyError::Cancelled`, a second signal terminates
+/// gifsy at once
+pub fn handle_signals() -> io::Result<()> {
+    for &signal in &[SIGINT, SIGTERM]
+    {
+        flag::register_conditional_shutdown(signal, 1, Arc::clone(cancelled_flag()))?;
+        flag::register(signal, Arc::clone(cancelled_flag()))?;
+    }
+    Ok(())
+}
+
+pub fn cancel() {
+    cancelled_flag().store(true, Ordering::SeqCst);
+}
+
+pub fn cancelled() -> bool {
+    cancelled_flag().load(Ordering::SeqCst)
+}
+
+/// sleeps for `duration`, fails early once gifsy is cancelled
+pub fn sleep(duration: Duration) -> Result<(), GifsyError> {
+    let end = Instant::now() + duration;
+    loop
+    {
+        if cancelled()
+        {
+            return Err(GifsyError::Cancelled);
+        }
+        let now = Instant::now();
+        if now >= end
+        {
+            return Ok(());
+        }
+        thread::sleep(POLL.min(end - now));
+    }
+}
+
+/// Runs `cmd` in a process group of its own with `input` on its standard
+/// input and collects its output. If it takes longer than `timeout` or
+/// gifsy is cancelled the whole group is terminated, so helpers like ssh
+/// stop too.
+pub fn output(mut cmd: Command, input: Option<Vec<u8>>, op: &str, timeout: Duration) -> Result<Output, GifsyError> {
+    if cancelled()
+    {
+        return Err(GifsyError::Cancelled);
+    }
+    cmd.process_group(0)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = cmd.spawn().map_err(GifsyError::IoError)?;
+    let writer = child.stdin.take().map(|mut stdin| {
+        let input = input.unwrap_or_default();
+        thread::spawn(move || stdin.write_all(&input))
+    });
+    let stdout = reader(child.stdout.take());
+    let stderr = reader(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop
+    {
+        if let Some(status) = child.try_wait().map_err(GifsyError::IoError)?
+        {
+            break status;
+        }
+        if cancelled()
+        {
+            info!("cancel git {}", op);
+            stop(&mut child);
+            return Err(GifsyError::Cancelled);
+        }
+        if Instant::now() >= deadline
+        {
+            warn!("git {} takes longer than {:?}, stop it", op, timeout);
+            stop(&mut child);
+            return Err(GifsyError::Timeout(op.to_string()));
+        }
+        thread::sleep(POLL);
+    };
+    // git may exit without reading all of its input
+    match writer.map(|w| w.join())
+    {
+        Some(Ok(Err(e))) if e.kind() != ErrorKind::BrokenPipe => return Err(GifsyError::IoError(e)),
+        _ => (),
+    }
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn reader<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut p) = pipe
+        {
+            let _ = p.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// terminates the process group of `child`, it is killed if it doesn't
+/// end within the grace time
+fn stop(child: &mut Child) {
+    let group = child.id() as libc::pid_t;
+    unsafe {
+        libc::kill(-group, libc::SIGTERM);
+    }
+    let end = Instant::now() + GRACE;
+    while Instant::now() < end
+    {
+        match child.try_wait()
+        {
+            Ok(None) => thread::sleep(POLL),
+            _ => return,
+        }
+    }
+    unsafe {
+        libc::kill(-group, libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeout() {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("sleep 10 & wait");
+        let start = Instant::now();
+        match output(cmd, None, "sleep", Duration::from_millis(200))
+        {
+            Err(GifsyError::Timeout(ref op)) if op == "sleep" => (),
+            r => panic!("no timeout {:?}", r),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let mut cmd = Command::new("cat");
+        cmd.arg("-");
+        let out = output(cmd, Some(b"input".to_vec()), "cat", Duration::from_secs(5)).unwrap();
+        assert!(out.status.success());
+        assert_eq!(out.stdout, b"input");
+    }
+}
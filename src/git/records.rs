@@ -0,0 +1,274 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use super::parser::*;
+use super::paths;
+
+/// the format of `git log -z` `Commit::parse_log` reads, the fields end
+/// with a zero-byte like the records do
+pub const LOG_FORMAT: &str = "--format=%H%x00%h%x00%an%x00%at%x00%s";
+
+/// a commit as listed by `git log`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub id: String,
+    pub short_id: String,
+    pub author: String,
+    /// the author time in seconds since the epoch
+    pub time: i64,
+    pub subject: String,
+}
+
+impl Commit {
+    /// the commits of `git log -z` with `LOG_FORMAT`
+    pub fn parse_log(input: &[u8]) -> Result<Vec<Commit>, ParseError> {
+        many(input, |s| {
+            let (id, s) = oid(s, Field::Oid)?;
+            let ((), s) = tag(s, b"\0", Field::Oid)?;
+            let (short_id, s) = oid(s, Field::Oid)?;
+            let ((), s) = tag(s, b"\0", Field::Oid)?;
+            let (author, s) = text(s, 0, Field::Author)?;
+            let (time, s) = number(s, 0, Field::Time)?;
+            let (subject, s) = text(s, 0, Field::Subject)?;
+            Ok((
+                Commit {
+                    id,
+                    short_id,
+                    author,
+                    time,
+                    subject,
+                },
+                s,
+            ))
+        })
+    }
+}
+
+impl fmt::Display for Commit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.short_id, self.subject)
+    }
+}
+
+/// the lines a diff adds to and deletes from a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    /// `None` for a binary file
+    pub added: Option<u32>,
+    pub deleted: Option<u32>,
+    pub path: PathBuf,
+    /// the source of a rename or a copy
+    pub from: Option<PathBuf>,
+}
+
+impl FileStat {
+    /// the files of `git diff --numstat -z`
+    pub fn parse_numstat(input: &[u8]) -> Result<Vec<FileStat>, ParseError> {
+        many(input, |s| {
+            let (added, s) = count(s)?;
+            let (deleted, s) = count(s)?;
+            // a rename leaves the path empty and both paths follow
+            let (from, s) = match tag(s, b"\0", Field::Path)
+            {
+                Ok(((), s)) =>
+                {
+                    let (from, s) = until(s, 0, Field::RenameSource)?;
+                    (Some(paths::from_bytes(from)), s)
+                }
+                Err(_) => (None, s),
+            };
+            let (path, s) = until(s, 0, Field::Path)?;
+            Ok((
+                FileStat {
+                    added,
+                    deleted,
+                    path: paths::from_bytes(path),
+                    from,
+                },
+                s,
+            ))
+        })
+    }
+}
+
+/// a line count of `--numstat`, `-` for a binary file
+fn count(s: &[u8]) -> Parsed<'_, Option<u32>> {
+    match tag(s, b"-\t", Field::Count)
+    {
+        Ok(((), s)) => Ok((None, s)),
+        Err(_) => number(s, b'\t', Field::Count).map(|(n, s)| (Some(n), s)),
+    }
+}
+
+impl fmt::Display for FileStat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.added, self.deleted)
+        {
+            (Some(added), Some(deleted)) => write!(f, "+{} -{} ", added, deleted)?,
+            _ => write!(f, "binary ")?,
+        }
+        if let Some(ref from) = self.from
+        {
+            write!(f, "{} -> ", paths::escape(from))?;
+        }
+        write!(f, "{}", paths::escape(&self.path))
+    }
+}
+
+/// a ref of a remote as listed by `git ls-remote`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRef {
+    pub oid: String,
+    /// the full name like `refs/heads/main`
+    pub name: String,
+}
+
+impl RemoteRef {
+    /// the refs of `git ls-remote`
+    pub fn parse_ls_remote(input: &[u8]) -> Result<Vec<RemoteRef>, ParseError> {
+        many(input, |s| {
+            let (oid, s) = oid(s, Field::Oid)?;
+            let ((), s) = tag(s, b"\t", Field::Ref)?;
+            let (name, s) = text(s, b'\n', Field::Ref)?;
+            Ok((RemoteRef { oid, name }, s))
+        })
+    }
+}
+
+/// how the checked out commit of a submodule relates to the recorded one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmoduleState {
+    Current,
+    Uninitialized,
+    /// another commit is checked out
+    Modified,
+    /// the submodule has merge conflicts
+    Conflicted,
+}
+
+impl SubmoduleState {
+    /// the letter of `git submodule status`
+    pub fn flag(self) -> char {
+        match self
+        {
+            SubmoduleState::Current => ' ',
+            SubmoduleState::Uninitialized => '-',
+            SubmoduleState::Modified => '+',
+            SubmoduleState::Conflicted => 'U',
+        }
+    }
+}
+
+/// the commit checked out in a submodule as listed by
+/// `git submodule status`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmoduleCommit {
+    pub state: SubmoduleState,
+    pub oid: String,
+    pub path: PathBuf,
+    /// the commit described by a tag or a branch, `None` if it isn't
+    /// checked out
+    pub describe: Option<String>,
+}
+
+impl SubmoduleCommit {
+    /// the submodules of `git submodule status`
+    pub fn parse_status(input: &[u8]) -> Result<Vec<SubmoduleCommit>, ParseError> {
+        many(input, |s| {
+            let (flag, s) = one_of(s, " -+U", Field::State)?;
+            let state = match flag
+            {
+                '-' => SubmoduleState::Uninitialized,
+                '+' => SubmoduleState::Modified,
+                'U' => SubmoduleState::Conflicted,
+                _ => SubmoduleState::Current,
+            };
+            let (oid, s) = oid(s, Field::Oid)?;
+            let ((), s) = tag(s, b" ", Field::Path)?;
+            let (line, s) = until(s, b'\n', Field::Path)?;
+            let (path, describe) = match line.windows(2).rposition(|w| w == b" (")
+            {
+                Some(pos) if line.ends_with(b")") =>
+                {
+                    let describe = String::from_utf8_lossy(&line[pos + 2..line.len() - 1]);
+                    (&line[..pos], Some(describe.into_owned()))
+                }
+                _ => (line, None),
+            };
+            Ok((
+                SubmoduleCommit {
+                    state,
+                    oid,
+                    path: paths::from_bytes(path),
+                    describe,
+                },
+                s,
+            ))
+        })
+    }
+}
+
+impl fmt::Display for SubmoduleCommit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{} {}", self.state.flag(), self.oid, paths::escape(&self.path))?;
+        match self.describe
+        {
+            Some(ref d) => write!(f, " ({})", d),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH1: &str = "1111111111111111111111111111111111111111";
+    const HASH2: &str = "2222222222222222222222222222222222222222";
+
+    #[test]
+    fn test_parse_log() {
+        let input = format!("{}\x001111111\x00Jo Doe\x001700000000\x00first\x00", HASH1);
+        let input = format!("{}{}\x002222222\x00Jo\x001700000001\x00\x00", input, HASH2);
+        let commits = Commit::parse_log(input.as_bytes()).unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].author, "Jo Doe");
+        assert_eq!(commits[0].time, 1700000000);
+        assert_eq!(commits[0].to_string(), "1111111 first");
+        assert_eq!(commits[1].subject, "");
+        let e = Commit::parse_log(format!("{}\x00111\x00", HASH1).as_bytes()).unwrap_err();
+        assert_eq!((e.field, e.offset), (Field::Oid, 41));
+    }
+
+    #[test]
+    fn test_parse_numstat() {
+        let stats = FileStat::parse_numstat(b"3\t1\ta.txt\x00-\t-\tlogo.png\x000\t0\t\x00old\x00new\x00").unwrap();
+        assert_eq!(
+            stats.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+            ["+3 -1 a.txt", "binary logo.png", "+0 -0 old -> new"]
+        );
+        let e = FileStat::parse_numstat(b"3\tx\ta.txt\x00").unwrap_err();
+        assert_eq!((e.field, e.offset), (Field::Count, 2));
+    }
+
+    #[test]
+    fn test_parse_ls_remote() {
+        let input = format!("{}\tHEAD\n{}\trefs/heads/main\n", HASH1, HASH2);
+        let refs = RemoteRef::parse_ls_remote(input.as_bytes()).unwrap();
+        assert_eq!(refs[1], RemoteRef { oid: HASH2.to_string(), name: "refs/heads/main".to_string() });
+        let e = RemoteRef::parse_ls_remote(b"warning: no refs\n").unwrap_err();
+        assert_eq!((e.field, e.offset), (Field::Oid, 0));
+    }
+
+    #[test]
+    fn test_parse_submodule_status() {
+        let input = format!(" {} lib (v1.0)\n-{} my lib\n+{} (x) (heads/main)\n", HASH1, HASH2, HASH1);
+        let submodules = SubmoduleCommit::parse_status(input.as_bytes()).unwrap();
+        assert_eq!(submodules[0].describe.as_deref(), Some("v1.0"));
+        assert_eq!(submodules[1].state, SubmoduleState::Uninitialized);
+        assert_eq!(submodules[1].path, PathBuf::from("my lib"));
+        assert_eq!(submodules[1].describe, None);
+        assert_eq!(submodules[2].path, PathBuf::from("(x)"));
+        assert_eq!(submodules[2].to_string(), format!("+{} (x) (heads/main)", HASH1));
+    }
+}
@@ -0,0 +1,444 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::parser::*;
+use super::paths;
+
+/// The branch, the changes and the submodule states of a repository as
+/// reported by `git status --porcelain=v2 --branch -z`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub branch: Branch,
+    pub entries: Vec<Entry>,
+}
+
+/// the `# branch.*` headers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Branch {
+    /// the commit of HEAD, `None` before the first commit
+    pub oid: Option<String>,
+    /// the checked out branch, `None` if HEAD is detached
+    pub head: Option<String>,
+    /// the upstream branch like `origin/main`
+    pub upstream: Option<String>,
+    /// the commits ahead of and behind the upstream branch, `None`
+    /// without upstream or if it is gone
+    pub ahead_behind: Option<(u32, u32)>,
+}
+
+/// the state of a submodule in a change
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Submodule {
+    /// another commit than the recorded one is checked out
+    pub commit_changed: bool,
+    /// tracked files of the submodule are modified
+    pub modified: bool,
+    /// the submodule has untracked files
+    pub untracked: bool,
+}
+
+/// a changed path, the flags are the ones of git with `.` for unchanged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub index: char,
+    pub tree: char,
+    /// `None` if the path is no submodule
+    pub submodule: Option<Submodule>,
+    /// the modes in HEAD, the index and the working tree
+    pub modes: [u32; 3],
+    /// the object ids in HEAD and the index
+    pub oids: [String; 2],
+    pub path: PathBuf,
+}
+
+/// one record of the status
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    Changed(Change),
+    /// a renamed or copied path, `score` is the similarity in percent if
+    /// the backend knows it
+    Renamed {
+        change: Change,
+        copied: bool,
+        score: Option<u8>,
+        from: PathBuf,
+    },
+    /// a path with conflicts, modes and object ids are the ones of the
+    /// common ancestor, ours and theirs, the working tree mode comes last
+    Unmerged {
+        index: char,
+        tree: char,
+        submodule: Option<Submodule>,
+        modes: [u32; 4],
+        oids: [String; 3],
+        path: PathBuf,
+    },
+    Untracked(PathBuf),
+    Ignored(PathBuf),
+}
+
+impl Entry {
+    pub fn path(&self) -> &Path {
+        match *self
+        {
+            Entry::Changed(ref c) | Entry::Renamed { change: ref c, .. } => &c.path,
+            Entry::Unmerged { ref path, .. } | Entry::Untracked(ref path) | Entry::Ignored(ref path) => path,
+        }
+    }
+    pub fn submodule(&self) -> Option<Submodule> {
+        match *self
+        {
+            Entry::Changed(ref c) | Entry::Renamed { change: ref c, .. } => c.submodule,
+            Entry::Unmerged { submodule, .. } => submodule,
+            Entry::Untracked(_) | Entry::Ignored(_) => None,
+        }
+    }
+}
+
+/// like `git status --short`, submodules tell what changed in them
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = |x: char, y: char| format!("{}{}", x, y).replace('.', " ");
+        match *self
+        {
+            Entry::Changed(ref c) => write!(f, "{} {}", flags(c.index, c.tree), paths::escape(&c.path))?,
+            Entry::Renamed {
+                ref change,
+                ref from,
+                ..
+            } => write!(
+                f,
+                "{} {} -> {}",
+                flags(change.index, change.tree),
+                paths::escape(from),
+                paths::escape(&change.path)
+            )?,
+            Entry::Unmerged {
+                index,
+                tree,
+                ref path,
+                ..
+            } => write!(f, "{} {}", flags(index, tree), paths::escape(path))?,
+            Entry::Untracked(ref path) => write!(f, "?? {}", paths::escape(path))?,
+            Entry::Ignored(ref path) => write!(f, "!! {}", paths::escape(path))?,
+        }
+        if let Some(s) = self.submodule()
+        {
+            let states = [
+                (s.commit_changed, "new commits"),
+                (s.modified, "modified content"),
+                (s.untracked, "untracked content"),
+            ];
+            let states: Vec<&str> = states.iter().filter(|s| s.0).map(|s| s.1).collect();
+            if !states.is_empty()
+            {
+                write!(f, " ({})", states.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Report {
+    /// parses the output of `git status --porcelain=v2 --branch -z`
+    pub fn parse(output: &[u8]) -> Result<Report, ParseError> {
+        let mut branch = Branch::default();
+        let entries = many(output, |s| record(s, &mut branch))?;
+        Ok(Report {
+            branch,
+            entries: entries.into_iter().flatten().collect(),
+        })
+    }
+    /// true if nothing is changed, ignored files don't count
+    pub fn is_clean(&self) -> bool {
+        self.entries.iter().all(|e| matches!(*e, Entry::Ignored(_)))
+    }
+    /// the commits not pushed yet, 0 without upstream
+    pub fn ahead(&self) -> u32 {
+        self.branch.ahead_behind.map_or(0, |(a, _)| a)
+    }
+    /// the commits not pulled yet, 0 without upstream
+    pub fn behind(&self) -> u32 {
+        self.branch.ahead_behind.map_or(0, |(_, b)| b)
+    }
+    pub fn unmerged(&self) -> Vec<&Path> {
+        self.entries
+            .iter()
+            .filter(|e| matches!(**e, Entry::Unmerged { .. }))
+            .map(|e| e.path())
+            .collect()
+    }
+    /// the paths of the submodules with changes
+    pub fn submodules(&self) -> Vec<(&Path, Submodule)> {
+        self.entries
+            .iter()
+            .filter_map(|e| e.submodule().map(|s| (e.path(), s)))
+            .collect()
+    }
+}
+
+impl Branch {
+    /// a `# branch.*` header, the ones gifsy doesn't know like `# stash`
+    /// are skipped
+    fn header<'a>(&mut self, s: &'a [u8]) -> Parsed<'a, ()> {
+        let (key, s) = match word(s, Field::Header)
+        {
+            Ok(k) => k,
+            Err(_) => return skip(s),
+        };
+        let value = |s| text(s, 0, Field::Header);
+        match key
+        {
+            b"branch.oid" =>
+            {
+                let (oid, rest) = value(s)?;
+                self.oid = Some(oid).filter(|v| v != "(initial)");
+                Ok(((), rest))
+            }
+            b"branch.head" =>
+            {
+                let (head, rest) = value(s)?;
+                self.head = Some(head).filter(|v| v != "(detached)");
+                Ok(((), rest))
+            }
+            b"branch.upstream" =>
+            {
+                let (upstream, rest) = value(s)?;
+                self.upstream = Some(upstream);
+                Ok(((), rest))
+            }
+            b"branch.ab" =>
+            {
+                let ((), s) = tag(s, b"+", Field::Count)?;
+                let (ahead, s) = number(s, b' ', Field::Count)?;
+                let ((), s) = tag(s, b"-", Field::Count)?;
+                let (behind, rest) = number(s, 0, Field::Count)?;
+                self.ahead_behind = Some((ahead, behind));
+                Ok(((), rest))
+            }
+            _ => skip(s),
+        }
+    }
+}
+
+/// the flags of porcelain v2 with `.` for unchanged
+const FLAGS: &str = ".MTADRCU";
+
+/// one record, headers fill in `branch` and give no entry
+fn record<'a>(s: &'a [u8], branch: &mut Branch) -> Parsed<'a, Option<Entry>> {
+    let (kind, s) = one_of(s, "#12u?!", Field::Kind)?;
+    let ((), s) = tag(s, b" ", Field::Kind)?;
+    match kind
+    {
+        '#' => branch.header(s).map(|((), rest)| (None, rest)),
+        '1' =>
+        {
+            let (mut change, s) = change(s)?;
+            let (path, s) = until(s, 0, Field::Path)?;
+            change.path = paths::from_bytes(path);
+            Ok((Some(Entry::Changed(change)), s))
+        }
+        '2' =>
+        {
+            let (mut change, s) = change(s)?;
+            let (copied, s) = one_of(s, "RC", Field::Score)?;
+            let (score, s) = number(s, b' ', Field::Score)?;
+            let (path, s) = until(s, 0, Field::Path)?;
+            // with -z the original path is a record of its own
+            let (from, s) = until(s, 0, Field::RenameSource)?;
+            change.path = paths::from_bytes(path);
+            let entry = Entry::Renamed {
+                change,
+                copied: copied == 'C',
+                score: Some(score),
+                from: paths::from_bytes(from),
+            };
+            Ok((Some(entry), s))
+        }
+        'u' =>
+        {
+            let ((index, tree), s) = xy(s)?;
+            let (submodule, s) = submodule(s)?;
+            let (base, s) = mode(s)?;
+            let (ours, s) = mode(s)?;
+            let (theirs, s) = mode(s)?;
+            let (worktree, s) = mode(s)?;
+            let (base_oid, s) = object(s)?;
+            let (our_oid, s) = object(s)?;
+            let (their_oid, s) = object(s)?;
+            let (path, s) = until(s, 0, Field::Path)?;
+            let entry = Entry::Unmerged {
+                index,
+                tree,
+                submodule,
+                modes: [base, ours, theirs, worktree],
+                oids: [base_oid, our_oid, their_oid],
+                path: paths::from_bytes(path),
+            };
+            Ok((Some(entry), s))
+        }
+        '?' => until(s, 0, Field::Path).map(|(p, s)| (Some(Entry::Untracked(paths::from_bytes(p))), s)),
+        _ => until(s, 0, Field::Path).map(|(p, s)| (Some(Entry::Ignored(paths::from_bytes(p))), s)),
+    }
+}
+
+/// the fields of an ordinary or renamed record from the flags up to the
+/// object ids, the path is left empty
+fn change(s: &[u8]) -> Parsed<'_, Change> {
+    let ((index, tree), s) = xy(s)?;
+    let (submodule, s) = submodule(s)?;
+    let (head, s) = mode(s)?;
+    let (staged, s) = mode(s)?;
+    let (worktree, s) = mode(s)?;
+    let (head_oid, s) = object(s)?;
+    let (index_oid, s) = object(s)?;
+    Ok((
+        Change {
+            index,
+            tree,
+            submodule,
+            modes: [head, staged, worktree],
+            oids: [head_oid, index_oid],
+            path: PathBuf::new(),
+        },
+        s,
+    ))
+}
+
+fn xy(s: &[u8]) -> Parsed<'_, (char, char)> {
+    let (x, rest) = one_of(s, FLAGS, Field::IndexFlag)?;
+    let (y, rest) = one_of(rest, FLAGS, Field::TreeFlag)?;
+    let ((), rest) = tag(rest, b" ", Field::TreeFlag)?;
+    Ok(((x, y), rest))
+}
+
+/// `N...` for no submodule, otherwise `S` and the flags `C`, `M` and `U`
+fn submodule(s: &[u8]) -> Parsed<'_, Option<Submodule>> {
+    let (flags, rest) = word(s, Field::Submodule)?;
+    match *flags
+    {
+        [b'N', b'.', b'.', b'.'] => Ok((None, rest)),
+        [b'S', c, m, u] => Ok((
+            Some(Submodule {
+                commit_changed: c == b'C',
+                modified: m == b'M',
+                untracked: u == b'U',
+            }),
+            rest,
+        )),
+        _ => Err(ParseError::at(Field::Submodule, s)),
+    }
+}
+
+fn mode(s: &[u8]) -> Parsed<'_, u32> {
+    let (m, rest) = word(s, Field::Mode)?;
+    match std::str::from_utf8(m).ok().and_then(|m| u32::from_str_radix(m, 8).ok())
+    {
+        Some(m) => Ok((m, rest)),
+        None => Err(ParseError::at(Field::Mode, s)),
+    }
+}
+
+/// an object id followed by a space
+fn object(s: &[u8]) -> Parsed<'_, String> {
+    let (id, rest) = oid(s, Field::Oid)?;
+    let ((), rest) = tag(rest, b" ", Field::Oid)?;
+    Ok((id, rest))
+}
+
+/// skips the rest of the record
+fn skip(s: &[u8]) -> Parsed<'_, ()> {
+    until(s, 0, Field::Header).map(|(_, rest)| ((), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH1: &str = "1111111111111111111111111111111111111111";
+    const HASH2: &str = "2222222222222222222222222222222222222222";
+
+    #[test]
+    fn test_parse_branch() {
+        let output = "# branch.oid 3333333333333333333333333333333333333333\0\
+                      # branch.head main\0\
+                      # branch.upstream origin/main\0\
+                      # branch.ab +2 -1\0\
+                      # stash 1\0";
+        let report = Report::parse(output.as_bytes()).unwrap();
+        assert_eq!(report.branch.head, Some("main".to_string()));
+        assert_eq!(report.branch.upstream, Some("origin/main".to_string()));
+        assert_eq!((report.ahead(), report.behind()), (2, 1));
+        assert!(report.is_clean());
+
+        let report = Report::parse(b"# branch.oid (initial)\0# branch.head (detached)\0").unwrap();
+        assert_eq!(report.branch, Branch::default());
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        let output = format!(
+            "1 .M N... 100644 100644 100644 {h1} {h1} a.txt\0\
+             2 R. N... 100644 100644 100644 {h1} {h1} R100 new name.txt\0old name.txt\0\
+             1 .M SC.U 160000 160000 160000 {h1} {h1} lib\0\
+             u UU N... 100644 100644 100644 100644 {h1} {h2} {h1} conflict.txt\0\
+             ? new.txt\0\
+             ! build/\0",
+            h1 = HASH1,
+            h2 = HASH2
+        );
+        let report = Report::parse(output.as_bytes()).unwrap();
+        let paths: Vec<&Path> = report.entries.iter().map(|e| e.path()).collect();
+        assert_eq!(paths, ["a.txt", "new name.txt", "lib", "conflict.txt", "new.txt", "build/"].map(Path::new));
+        match report.entries[1]
+        {
+            Entry::Renamed {
+                ref from,
+                copied: false,
+                score: Some(100),
+                ..
+            } => assert_eq!(from, Path::new("old name.txt")),
+            ref e => panic!("not a rename {:?}", e),
+        }
+        match report.entries[0]
+        {
+            Entry::Changed(ref c) => assert_eq!((c.index, c.tree, c.modes[2]), ('.', 'M', 0o100644)),
+            ref e => panic!("not a change {:?}", e),
+        }
+        assert_eq!(report.unmerged(), [Path::new("conflict.txt")]);
+        let lib = Submodule {
+            commit_changed: true,
+            modified: false,
+            untracked: true,
+        };
+        assert_eq!(report.submodules(), [(Path::new("lib"), lib)]);
+        let lines: Vec<String> = report.entries.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            lines,
+            [
+                " M a.txt",
+                "R  old name.txt -> new name.txt",
+                " M lib (new commits, untracked content)",
+                "UU conflict.txt",
+                "?? new.txt",
+                "!! build/"
+            ]
+        );
+        assert!(!report.is_clean());
+
+        let report = Report::parse(b"? gr\xfc\xdfe.txt\0").unwrap();
+        assert_eq!(report.entries[0].path(), paths::from_bytes(b"gr\xfc\xdfe.txt"));
+        assert_eq!(report.entries[0].to_string(), "?? \"gr\\374\\337e.txt\"");
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Report::parse(b"1 .M N... 100644\0").is_err());
+        let e = Report::parse(format!("2 R. N... 100644 100644 100644 {h} {h} R100 new\0", h = HASH1).as_bytes());
+        assert_eq!(e.unwrap_err().field, Field::RenameSource);
+        let e = Report::parse(b"# branch.head main\0x what\0").unwrap_err();
+        assert_eq!((e.field, e.offset), (Field::Kind, 19));
+        let e = Report::parse(b"1 .M N... 100644 1x0644 100644\0").unwrap_err();
+        assert_eq!((e.field, e.offset), (Field::Mode, 17));
+    }
+}
@@ -1,8 +1,20 @@
 #[macro_use]
 extern crate log;
 extern crate chrono;
+#[cfg(feature = "libgit2")]
+extern crate git2;
+extern crate glob;
+extern crate libc;
 extern crate notify_rust;
+extern crate notify as fsnotify;
+#[macro_use]
+extern crate serde;
+extern crate signal_hook;
+extern crate toml;
 
+pub mod config;
 #[macro_use]
 pub mod git;
+#[allow(unexpected_cfgs)]
 pub mod notify;
+pub mod watch;
@@ -8,22 +8,32 @@ extern crate tracing_journald;
 
 extern crate gifsy;
 
-use std::env;
+use std::fmt;
 use std::path;
+use std::thread;
+use std::time::Duration;
 
-use clap::{App, AppSettings, Command, Arg, SubCommand};
+use clap::{App, AppSettings, Command, Arg, ArgGroup, SubCommand};
 //use flexi_logger::FileSpec;
 //use flexi_logger::{Duplicate, Logger, opt_format};
+use gifsy::config;
 use gifsy::git;
-use gifsy::git::GifsyError;
+use gifsy::git::{ConflictStrategy, GifsyError};
 use gifsy::notify;
+use gifsy::watch;
 use tracing_subscriber::prelude::*;
 
 #[derive(Debug, Clone)]
 enum MainError {
     SubcomamndUnknown,
     SubcommandNotFound,
+    Config,
     NoRepository,
+    WatchFailed,
+    Interrupted(String),
+    Locked(u32),
+    Cancelled,
+    Offline(String),
     GitFailed(i32, String),
 }
 
@@ -33,27 +43,85 @@ impl MainError {
         {
             MainError::SubcomamndUnknown => 1001,
             MainError::SubcommandNotFound => 1002,
+            MainError::Config => 1003,
             MainError::NoRepository => 1008,
+            MainError::WatchFailed => 1009,
+            MainError::Interrupted(_) => 1010,
+            MainError::Locked(_) => 1011,
+            MainError::Cancelled => 1012,
+            MainError::Offline(_) => 1013,
             MainError::GitFailed(c, _) => c,
         }
     }
 }
 
+impl fmt::Display for MainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self
+        {
+            MainError::SubcomamndUnknown => write!(f, "unknown subcommand"),
+            MainError::SubcommandNotFound => write!(f, "no subcommand"),
+            MainError::Config => write!(f, "invalid configuration"),
+            MainError::NoRepository => write!(f, "no repository"),
+            MainError::WatchFailed => write!(f, "watcher crashed"),
+            MainError::Interrupted(ref m) => write!(f, "{}", m),
+            MainError::Locked(pid) => write!(f, "repository locked by process {}", pid),
+            MainError::Cancelled => write!(f, "cancelled"),
+            MainError::Offline(ref m) => write!(f, "offline, {}", m),
+            MainError::GitFailed(c, ref m) => write!(f, "{} ({})", m, c),
+        }
+    }
+}
+
 impl From<GifsyError> for MainError {
     fn from(e: GifsyError) -> Self {
         match e
         {
             git::GifsyError::CmdFail(c, m) => MainError::GitFailed(c, m),
+            e @ git::GifsyError::Interrupted(_) => MainError::Interrupted(e.to_string()),
+            git::GifsyError::Locked(pid) => MainError::Locked(pid),
+            git::GifsyError::Cancelled => MainError::Cancelled,
+            git::GifsyError::Offline(e) => MainError::Offline(e),
             e => MainError::GitFailed(1008, e.to_string()),
         }
     }
 }
 fn main() {
+    // Work the command line arguments
+    let mut app = arguments();
+    let matches = app.clone().get_matches();
+
+    let cli = config::Layer {
+        repo: matches.value_of("repo").map(String::from),
+        name: matches.value_of("name").map(String::from),
+        remote: matches.value_of("remote").map(String::from),
+        branch: matches.value_of("branch").map(String::from),
+        notify: if matches.is_present("notify") { Some(true) } else { None },
+        log_level: matches.value_of("loglevel").map(String::from),
+        pull_strategy: matches.value_of("pull").and_then(|p| p.parse().ok()),
+        conflict: matches.value_of("conflict").and_then(|c| c.parse().ok()),
+        backend: matches.value_of("backend").and_then(|b| b.parse().ok()),
+        ..config::Layer::default()
+    };
+    let conf = match config::Config::load(&cli, matches.value_of("config").map(path::Path::new))
+    {
+        Ok(c) => c,
+        Err(e) =>
+        {
+            eprintln!("gifsy: {}", e);
+            std::process::exit(MainError::Config.code())
+        }
+    };
+    let level = conf
+        .log_level
+        .value
+        .parse::<tracing::Level>()
+        .unwrap_or(tracing::Level::INFO);
 
     let fmt_sub = tracing_subscriber::fmt()
       .with_ansi(true)
       .with_level(true)
-      .with_max_level(tracing::Level::INFO)
+      .with_max_level(level)
       .with_target(false)
       .with_writer(std::io::sink)
       .finish();
@@ -71,72 +139,95 @@ fn main() {
       }
     }.expect("couldn't init tracing");
 
-
-    let home = env::var("HOME")
-        .expect("HOME environemnt variable not found");
-    let host_env = match env::var("HOST")
-    {
-        Ok(h) => h,
-        Err(_) => String::from("Unknown Host"),
-    };
-    let name_env = match env::var("GIFSY_NAME")
+    if conf.notify.value
     {
-        Ok(h) => h,
-        Err(_) => host_env,
-    };
-    let repo_path_env = match env::var("GIFSY_REPO")
+        notify::enable();
+    }
+    if let Err(e) = git::process::handle_signals()
     {
-        Ok(h) => h,
-        Err(_) =>
-        {
-            debug!("use default repository path");
-            let mut defaultpath = Box::new(path::PathBuf::from(home));
-            defaultpath.push("Shared");
-            defaultpath.push("sync");
-            defaultpath.to_string_lossy().into_owned()
-        }
-    };
-    // Work the command line arguments
-    let mut app = arguments();
-    let matches = app.clone().get_matches();
+        warn!("can't handle signals {}", e);
+    }
+    info!("GIt FileSYncronization startet");
 
-    let repo = &match matches.value_of("repo")
-    {
-        Some(repo) => repo.to_string(),
-        None => repo_path_env,
-    };
-    let name = &match matches.value_of("name")
+    if matches.subcommand_name() == Some("config")
     {
-        Some(repo) => repo.to_string(),
-        None => name_env,
-    };
-    if matches.is_present("notify")
-    {
-        notify::enable();
+        println!("{}", conf);
+        std::process::exit(0);
     }
-    info!("GIt FileSYncronization startet");
 
-    debug!("use repository {}", repo);
-    let r = match git::Repository::from(repo, name)
+    let mut selected = conf.repos.clone();
+    if let Some(only) = matches.value_of("only")
     {
-        Ok(r) => r,
-        Err(e) =>
+        selected.retain(|r| r.id == only);
+        if selected.is_empty()
         {
-            notify::send(
-                "GIt FileSYncronization needs attension",
-                "gifsy sync needs some love",
-            );
-            error!("can't create repository{}", e);
+            error!("no repository {} configured", only);
             std::process::exit(MainError::NoRepository.code())
         }
-    };
+    }
+    let wait = matches
+        .value_of("wait")
+        .and_then(|w| w.parse::<u64>().ok())
+        .map_or(Duration::from_secs(0), Duration::from_secs);
+    let targets: Vec<Target> = selected.iter().map(|c| Target::open(c, wait)).collect();
 
     let ecode = match matches.subcommand_name()
     {
         Some(subcmd) => match subcmd
         {
-            "status" => status(&r),
-            "sync" => sync(&r),
+            "status" =>
+            {
+                let fetch = matches
+                    .subcommand_matches("status")
+                    .is_some_and(|m| m.is_present("fetch"));
+                each(&targets, |r| status(r, fetch))
+            }
+            "sync" =>
+            {
+                let dry_run = matches
+                    .subcommand_matches("sync")
+                    .is_some_and(|m| m.is_present("dryrun"));
+                if dry_run
+                {
+                    each(&targets, dry_sync)
+                }
+                else
+                {
+                    each(&targets, sync)
+                }
+            }
+            "recover" =>
+            {
+                let abort = matches
+                    .subcommand_matches("recover")
+                    .is_some_and(|m| m.is_present("abort"));
+                if abort
+                {
+                    each(&targets, recover_abort)
+                }
+                else
+                {
+                    each(&targets, recover_continue)
+                }
+            }
+            "watch" =>
+            {
+                let debounce = matches
+                    .subcommand_matches("watch")
+                    .and_then(|m| m.value_of("debounce"))
+                    .and_then(|d| d.parse::<u64>().ok())
+                    .unwrap_or(2);
+                watch_all(&targets, Duration::from_secs(debounce))
+            }
+            "daemon" =>
+            {
+                let interval = matches
+                    .subcommand_matches("daemon")
+                    .and_then(|m| m.value_of("interval"))
+                    .and_then(|i| i.parse::<u64>().ok())
+                    .unwrap_or(300);
+                daemon(&targets, Duration::from_secs(interval))
+            }
             n =>
             {
                 error!("unknown subcommand {} found", n);
@@ -157,13 +248,24 @@ fn main() {
             debug!("GIt FileSYncronization done");
             0
         }
+        Err(MainError::Cancelled) =>
+        {
+            info!("GIt FileSYncronization cancelled");
+            MainError::Cancelled.code()
+        }
+        Err(rc @ MainError::Offline(_)) =>
+        {
+            println!("{}", rc);
+            info!("GIt FileSYncronization {}", rc);
+            rc.code()
+        }
         Err(rc) =>
         {
             notify::send(
                 "GIt FileSYncronization needs attension",
                 "gifsy sync needs some love",
             );
-            error!("GIt FileSYncronization done with error {:?}", rc);
+            error!("GIt FileSYncronization done with error {}", rc);
             rc.code()
         }
     };
@@ -171,45 +273,391 @@ fn main() {
     std::process::exit(rc);
 }
 
-fn status(repo: &git::Repository) -> Result<(), MainError> {
-    debug!("check status");
+/// a configured repository, holds the error if it couldn't be opened
+struct Target {
+    id: String,
+    repo: Result<git::Repository, GifsyError>,
+}
 
-    let status = repo.status()?;
+impl Target {
+    fn open(conf: &config::RepoConfig, wait: Duration) -> Target {
+        debug!("use repository {} ({})", conf.id, conf.path.value);
+        Target {
+            id: conf.id.clone(),
+            repo: git::Repository::from(&conf.path.value, &conf.name.value).and_then(|r| {
+                let backend = conf.backend.value.open(&conf.path.value, conf.timeouts.value)?;
+                Ok(r.with_backend(backend)
+                    .with_remote(&conf.remote.value, conf.branch.value.as_deref())
+                    .with_pull_strategy(conf.pull_strategy.value)
+                    .with_lock_wait(wait)
+                    .with_conflicts(conf.conflict.value, conf.conflicts.clone()))
+            }),
+        }
+    }
+    fn run<F>(&self, cmd: F) -> Result<(), MainError>
+    where
+        F: Fn(&git::Repository) -> Result<(), MainError>,
+    {
+        match self.repo
+        {
+            Ok(ref r) => cmd(r),
+            Err(ref e) =>
+            {
+                error!("can't create repository {} {}", self.id, e);
+                Err(MainError::NoRepository)
+            }
+        }
+    }
+}
 
-    println!(
-        "{}",
-        git::create_commit_message(&status, &repo.name()).unwrap()
-    );
+/// runs `cmd` on every repository, a failing repository doesn't stop
+/// the others and the most severe error is returned
+fn each<F>(targets: &[Target], cmd: F) -> Result<(), MainError>
+where
+    F: Fn(&git::Repository) -> Result<(), MainError>,
+{
+    let mut results = Vec::new();
+    for t in targets
+    {
+        if git::process::cancelled()
+        {
+            return Err(MainError::Cancelled);
+        }
+        let result = t.run(&cmd);
+        if targets.len() > 1
+        {
+            match result
+            {
+                Ok(()) => println!("{}: ok", t.id),
+                Err(ref e @ MainError::Offline(_)) => println!("{}: {}", t.id, e),
+                Err(ref e) => println!("{}: failed, {}", t.id, e),
+            }
+        }
+        if let Err(ref e) = result
+        {
+            error!("{} failed {}", t.id, e);
+        }
+        results.push(result);
+    }
+    severest(results)
+}
+
+/// the first of the most severe errors, a repository that failed weighs
+/// more than a locked or an offline one
+fn severest(results: Vec<Result<(), MainError>>) -> Result<(), MainError> {
+    let severity = |e: &MainError| match *e
+    {
+        MainError::Offline(_) => 1,
+        MainError::Locked(_) => 2,
+        _ => 3,
+    };
+    let mut rc = Ok(());
+    for e in results.into_iter().filter_map(Result::err)
+    {
+        match rc
+        {
+            Err(ref worst) if severity(worst) >= severity(&e) => (),
+            _ => rc = Err(e),
+        }
+    }
+    rc
+}
+
+/// reports how healthy the synchronization of the repository is, with
+/// `fetch` the ahead and behind counts are brought up to date first
+fn status(repo: &git::Repository, fetch: bool) -> Result<(), MainError> {
+    debug!("check status");
+
+    println!("repository {}", repo.path().display());
+    let fetched = if fetch
+    {
+        debug!("fetch remote");
+        match repo.fetch()
+        {
+            Err(GifsyError::Offline(e)) =>
+            {
+                println!("can't fetch, remote unreachable {}", e);
+                false
+            }
+            r => r.map(|_| true)?,
+        }
+    }
+    else
+    {
+        false
+    };
+    let report = repo.report()?;
+    let branch = &report.branch;
+    match (&branch.head, &branch.oid)
+    {
+        (Some(h), _) => println!("on branch {}", h),
+        (None, Some(oid)) => println!("HEAD detached at {:.7}", oid),
+        (None, None) => println!("HEAD detached"),
+    }
+    match (&branch.upstream, branch.ahead_behind)
+    {
+        // a rebase detaches HEAD
+        _ if branch.head.is_none() => (),
+        (None, _) => println!("no upstream branch, {} commits not pushed yet", repo.to_push()),
+        (Some(u), None) => println!("upstream {} is gone", u),
+        (Some(u), Some((ahead, behind))) =>
+        {
+            let since = if fetched { "" } else { " as of the last fetch" };
+            println!("upstream {}, {} ahead, {} behind{}", u, ahead, behind, since);
+        }
+    }
+    if let Some(i) = repo.interrupted()?
+    {
+        println!("{}, run gifsy recover --abort or --continue", i);
+    }
+    match repo.last_sync()?
+    {
+        Some(l) => println!("last sync {}", l),
+        None => println!("never synchronized"),
+    }
+    let (unmerged, pending): (Vec<_>, Vec<_>) = report
+        .entries
+        .iter()
+        .filter(|e| !matches!(**e, git::Entry::Ignored(_)))
+        .partition(|e| matches!(**e, git::Entry::Unmerged { .. }));
+    if !unmerged.is_empty()
+    {
+        println!("unmerged files:");
+        for e in unmerged
+        {
+            println!("  {}", e);
+        }
+    }
+    if pending.is_empty()
+    {
+        println!("no pending changes");
+    }
+    else
+    {
+        println!("pending changes:");
+        for e in pending
+        {
+            println!("  {}", e);
+        }
+    }
     Ok(())
 }
 
 fn sync(repo: &git::Repository) -> Result<(), MainError> {
-    debug!("synchronize repository");
+    synced(repo.sync()?)
+}
+
+fn synced(synced: git::Synced) -> Result<(), MainError> {
+    match synced
+    {
+        git::Synced::Done => Ok(()),
+        git::Synced::Offline(unpushed) =>
+        {
+            Err(MainError::Offline(format!("{} commits not pushed yet", unpushed)))
+        }
+    }
+}
+
+fn recover_abort(repo: &git::Repository) -> Result<(), MainError> {
+    debug!("abort interrupted operation");
+
+    match repo.recover_abort()?
+    {
+        Some(i) => println!("aborted {}", i),
+        None => println!("nothing to recover"),
+    }
+    Ok(())
+}
+
+fn recover_continue(repo: &git::Repository) -> Result<(), MainError> {
+    debug!("continue interrupted operation");
+
+    match repo.recover_continue()?
+    {
+        Some(i) => println!("finished {}", i),
+        None => println!("nothing to recover"),
+    }
+    Ok(())
+}
+
+/// reports what `sync` would do without changing the repository, only
+/// the remote tracking branch gets fetched
+fn dry_sync(repo: &git::Repository) -> Result<(), MainError> {
+    debug!("dry run synchronization");
 
-    let mut status = repo.status()?;
-    if !status.is_empty()
+    println!("repository {}", repo.path().display());
+    let status = repo.status()?;
+    // the conflict strategies resolve unmerged files unless they are manual
+    let manual = |s: &git::Status| s.skip().is_some() && repo.conflict_strategy(s.file()) == ConflictStrategy::Manual;
+    let (skipped, staged): (Vec<_>, Vec<_>) = status.into_iter().partition(|s| manual(s));
+    println!("would stage:");
+    for s in &staged
+    {
+        if s.is_unmerged()
+        {
+            println!("{} (resolved with {})", s, repo.conflict_strategy(s.file()));
+        }
+        else
+        {
+            println!("{}", s);
+        }
+    }
+    if !skipped.is_empty()
+    {
+        println!("would skip:");
+        for s in &skipped
+        {
+            println!("{} ({})", s, s.skip().unwrap());
+        }
+    }
+    let mut msg = None;
+    if !staged.is_empty()
     {
-        debug!("add local changes");
-        repo.add(status)?;
-        debug!("update local status");
-        status = repo.status()?;
-        info!("commit local changes");
-        repo.commit(status)?;
+        println!("would commit:");
+        let m = git::create_commit_message(&staged, &repo.name()).unwrap();
+        for l in m.lines()
+        {
+            println!("  {}", l);
+        }
+        msg = Some(m);
+    }
+    debug!("fetch remote");
+    repo.fetch()?;
+    if repo.has_upstream()?
+    {
+        println!("would pull:");
+        for c in repo.incoming()?
+        {
+            println!("  {}", c);
+        }
+        for f in repo.incoming_changes()?
+        {
+            println!("    {}", f);
+        }
+        println!("would push:");
+        for c in repo.outgoing()?
+        {
+            println!("  {}", c);
+        }
     }
     else
     {
-        debug!("no local changes");
+        // like sync, the first push sets the upstream branch
+        match repo.remote_head()?
+        {
+            Some(_) => println!("would pull the remote branch of the same name"),
+            None => println!("would pull nothing, the remote branch doesn't exist yet"),
+        }
+        println!("would push and set the upstream branch:");
+        println!("  {} commits", repo.to_push());
+    }
+    // the commit sync creates is pushed too
+    if let Some(m) = msg
+    {
+        println!("  (new) {}", m.lines().next().unwrap_or_default());
+    }
+    let submodules = repo.submodules_pending()?;
+    if !submodules.is_empty()
+    {
+        println!("would update submodules:");
+        for s in submodules
+        {
+            println!("  {}", s);
+        }
     }
-    info!("pull changes");
-    repo.pull()?;
-    debug!("handle submodules");
-    repo.submodules_init()?;
-    repo.submodules_update()?;
-    info!("push changes");
-    repo.push()?;
     Ok(())
 }
 
+fn watch_all(targets: &[Target], debounce: Duration) -> Result<(), MainError> {
+    thread::scope(|s| {
+        let watchers: Vec<_> = targets
+            .iter()
+            .map(|t| s.spawn(move || t.run(|r| watch(r, debounce))))
+            .collect();
+        let mut rc = Ok(());
+        for w in watchers
+        {
+            let result = w.join().unwrap_or(Err(MainError::WatchFailed));
+            if rc.is_ok()
+            {
+                rc = result;
+            }
+        }
+        rc
+    })
+}
+
+fn watch(repo: &git::Repository, debounce: Duration) -> Result<(), MainError> {
+    debug!("watch repository");
+
+    let watcher = watch::Watcher::new(repo, debounce)?;
+    info!("initial synchronization");
+    if let Err(e) = sync(repo)
+    {
+        cycle_failed(e)?;
+    }
+    loop
+    {
+        let changed = watcher.wait(repo)?;
+        info!("{} files changed, synchronize", changed.len());
+        if let Err(e) = sync(repo)
+        {
+            cycle_failed(e)?;
+        }
+    }
+}
+
+fn daemon(targets: &[Target], interval: Duration) -> Result<(), MainError> {
+    debug!("run as daemon every {:?}", interval);
+
+    loop
+    {
+        for t in targets
+        {
+            if let Err(e) = t.run(poll)
+            {
+                cycle_failed(e)?;
+            }
+        }
+        git::process::sleep(interval)?;
+    }
+}
+
+fn poll(repo: &git::Repository) -> Result<(), MainError> {
+    synced(repo.poll()?)
+}
+
+/// reports a failed synchronization cycle of a long running command, a
+/// cancellation ends the command
+fn cycle_failed(e: MainError) -> Result<(), MainError> {
+    match e
+    {
+        MainError::Cancelled => return Err(e),
+        MainError::Offline(_) =>
+        {
+            info!("{}, try again next time", e);
+            return Ok(());
+        }
+        _ => (),
+    }
+    notify::send(
+        "GIt FileSYncronization needs attension",
+        "gifsy sync needs some love",
+    );
+    error!("synchronization failed {}", e);
+    Ok(())
+}
+
+/// an interval of 0 seconds would poll in a busy loop
+fn interval(i: &str) -> Result<(), String> {
+    match i.parse::<u64>()
+    {
+        Ok(0) => Err(String::from("the interval must be at least one second")),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 fn arguments<'a>() -> App<'a> {
     Command::new("gifsy")
         .author("Dafo with the golden Hair <dafo@e6z9r.net>")
@@ -232,6 +680,76 @@ fn arguments<'a>() -> App<'a> {
                 .takes_value(true)
                 .help("Sets the name to identify the host"),
         )
+        .arg(
+            Arg::with_name("only")
+                .long("only")
+                .value_name("NAME")
+                .takes_value(true)
+                .help("Restricts the command to the named repository"),
+        )
+        .arg(
+            Arg::with_name("wait")
+                .short('w')
+                .long("wait")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .validator(|w| w.parse::<u64>())
+                .help("Sets how long to wait for a locked repository"),
+        )
+        .arg(
+            Arg::with_name("remote")
+                .long("remote")
+                .value_name("REMOTE")
+                .takes_value(true)
+                .help("Sets the remote to synchronize with"),
+        )
+        .arg(
+            Arg::with_name("branch")
+                .short('b')
+                .long("branch")
+                .value_name("BRANCH")
+                .takes_value(true)
+                .help("Sets the branch to synchronize"),
+        )
+        .arg(
+            Arg::with_name("pull")
+                .long("pull-strategy")
+                .value_name("STRATEGY")
+                .takes_value(true)
+                .possible_values(["rebase", "merge", "ff-only"])
+                .help("Sets how remote changes get integrated"),
+        )
+        .arg(
+            Arg::with_name("conflict")
+                .long("conflict")
+                .value_name("STRATEGY")
+                .takes_value(true)
+                .possible_values(["manual", "ours", "theirs", "local", "remote", "keep-both"])
+                .help("Sets how unmerged files get resolved"),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .value_name("BACKEND")
+                .takes_value(true)
+                .possible_values(["cli", "libgit2"])
+                .help("Sets how git is accessed, libgit2 needs the libgit2 feature"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Sets the configuration file"),
+        )
+        .arg(
+            Arg::with_name("loglevel")
+                .long("log-level")
+                .value_name("LEVEL")
+                .takes_value(true)
+                .help("Sets the log level (error, warn, info, debug, trace)"),
+        )
         .arg(
             Arg::with_name("logdir")
                 .short('l')
@@ -246,6 +764,107 @@ fn arguments<'a>() -> App<'a> {
                 .takes_value(false)
                 .help("enables desktop notification"),
         )
-        .subcommand(SubCommand::with_name("sync").about("Synchronize the repository"))
-        .subcommand(SubCommand::with_name("status").about("Status of the repository"))
+        .subcommand(
+            SubCommand::with_name("sync")
+                .about("Synchronize the repository")
+                .arg(
+                    Arg::with_name("dryrun")
+                        .long("dry-run")
+                        .takes_value(false)
+                        .help("Shows what would be done without doing it"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Status of the repository")
+                .arg(
+                    Arg::with_name("fetch")
+                        .long("fetch")
+                        .takes_value(false)
+                        .help("Fetches the remote branch before counting ahead and behind"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("recover")
+                .about("Finish or undo a rebase or autostash a sync left behind")
+                .arg(
+                    Arg::with_name("abort")
+                        .long("abort")
+                        .takes_value(false)
+                        .help("Returns to the state before the pull"),
+                )
+                .arg(
+                    Arg::with_name("continue")
+                        .long("continue")
+                        .takes_value(false)
+                        .help("Stages resolved files and finishes the operation"),
+                )
+                .group(
+                    ArgGroup::with_name("mode")
+                        .args(&["abort", "continue"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Inspect the configuration")
+                .setting(AppSettings::SubcommandRequired)
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Show the effective configuration and where it came from"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Watch the repository and synchronize on changes")
+                .arg(
+                    Arg::with_name("debounce")
+                        .short('d')
+                        .long("debounce")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .default_value("2")
+                        .validator(|d| d.parse::<u64>())
+                        .help("Sets the quiet time to wait for after a change"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("daemon")
+                .about("Periodically synchronize the repository")
+                .arg(
+                    Arg::with_name("interval")
+                        .short('i')
+                        .long("interval")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .default_value("300")
+                        .validator(interval)
+                        .help("Sets the time between two checks"),
+                ),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_severest() {
+        assert!(severest(vec![Ok(()), Ok(())]).is_ok());
+        let offline = || Err(MainError::Offline("no network".to_string()));
+        let failed = |c| Err(MainError::GitFailed(c, "failed".to_string()));
+        let rc = severest(vec![offline(), Ok(()), failed(1), failed(2)]);
+        assert_eq!(rc.unwrap_err().code(), 1);
+        let rc = severest(vec![offline(), Err(MainError::Locked(7))]);
+        assert_eq!(rc.unwrap_err().code(), 1011);
+        assert_eq!(severest(vec![Ok(()), offline()]).unwrap_err().code(), 1013);
+    }
+
+    #[test]
+    fn test_interval() {
+        let daemon = |i| arguments().try_get_matches_from(vec!["gifsy", "daemon", "--interval", i]);
+        assert!(daemon("60").is_ok());
+        assert!(daemon("0").is_err());
+        assert!(daemon("-1").is_err());
+    }
 }
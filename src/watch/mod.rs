@@ -0,0 +1,121 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use fsnotify::event::{AccessKind, AccessMode, EventKind};
+use fsnotify::{Event, RecommendedWatcher, RecursiveMode, Watcher as FsWatcher};
+
+use git::{process, GifsyError, Repository};
+
+/// how often waiting for changes checks if gifsy is cancelled
+const CANCEL_POLL: Duration = Duration::from_millis(500);
+
+/// Watches the working tree of a repository and reports bursts of
+/// changes once the tree has been quiet for the debounce time.
+pub struct Watcher {
+    root: PathBuf,
+    debounce: Duration,
+    events: Receiver<fsnotify::Result<Event>>,
+    _watcher: RecommendedWatcher,
+}
+
+impl Watcher {
+    pub fn new(repo: &Repository, debounce: Duration) -> Result<Watcher, GifsyError> {
+        let root = repo.path().canonicalize().map_err(GifsyError::IoError)?;
+        let (tx, rx) = channel();
+        let mut watcher = match fsnotify::recommended_watcher(tx)
+        {
+            Ok(w) => w,
+            Err(e) => return Err(GifsyError::WatchError(e.to_string())),
+        };
+        if let Err(e) = watcher.watch(&root, RecursiveMode::Recursive)
+        {
+            return Err(GifsyError::WatchError(e.to_string()));
+        }
+        debug!("watching {}", root.display());
+        Ok(Watcher {
+            root,
+            debounce,
+            events: rx,
+            _watcher: watcher,
+        })
+    }
+
+    /// Blocks until at least one file outside of `.git/` and not ignored
+    /// by git changed, and returns the changed paths relative to the
+    /// repository root.
+    pub fn wait(&self, repo: &Repository) -> Result<Vec<PathBuf>, GifsyError> {
+        loop
+        {
+            let mut changed = Vec::new();
+            match self.events.recv_timeout(CANCEL_POLL)
+            {
+                Ok(event) => self.collect(event, &mut changed),
+                Err(RecvTimeoutError::Timeout) if process::cancelled() => return Err(GifsyError::Cancelled),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) =>
+                {
+                    return Err(GifsyError::WatchError("watcher stopped".to_string()))
+                }
+            }
+            loop
+            {
+                match self.events.recv_timeout(self.debounce)
+                {
+                    Ok(event) => self.collect(event, &mut changed),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) =>
+                    {
+                        return Err(GifsyError::WatchError("watcher stopped".to_string()))
+                    }
+                }
+            }
+            changed.sort();
+            changed.dedup();
+            let ignored = repo.check_ignore(&changed)?;
+            changed.retain(|p| !ignored.contains(p));
+            if !changed.is_empty()
+            {
+                return Ok(changed);
+            }
+            debug!("only ignored files changed");
+        }
+    }
+
+    fn collect(&self, event: fsnotify::Result<Event>, changed: &mut Vec<PathBuf>) {
+        let event = match event
+        {
+            Ok(event) => event,
+            Err(e) =>
+            {
+                warn!("watch error {}", e);
+                return;
+            }
+        };
+        match event.kind
+        {
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => (),
+            EventKind::Access(_) => return,
+            _ => (),
+        }
+        for p in event.paths
+        {
+            if let Some(rel) = self.relative(&p)
+            {
+                changed.push(rel);
+            }
+        }
+    }
+
+    fn relative(&self, p: &Path) -> Option<PathBuf> {
+        let rel = p.strip_prefix(&self.root).ok()?;
+        if rel.as_os_str().is_empty() || rel.starts_with(".git")
+        {
+            None
+        }
+        else
+        {
+            Some(rel.to_path_buf())
+        }
+    }
+}